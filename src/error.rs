@@ -3,5 +3,49 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum ModError {
     #[error("Failed to get D3D9 device: {0}")]
-    GetDeviceFailed(String)
-}
\ No newline at end of file
+    GetDeviceFailed(String),
+    #[error("Invalid opcode table: {0}")]
+    InvalidOpcodeTable(String),
+    #[error("Invalid roster: {0}")]
+    InvalidRoster(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    #[error("Script is too small to hold a header ({0} bytes)")]
+    MissingHeader(usize),
+    #[error("Function table with {count} entries does not fit in a {size:#X} byte script")]
+    TableOutOfBounds { count: u32, size: usize },
+    #[error("Function `{name}` starts at {offset:#X}, past the end of the code section ({code_size:#X})")]
+    FunctionOutOfBounds {
+        name: String,
+        offset: u32,
+        code_size: usize,
+    },
+    #[error("Script has no functions")]
+    NoFunctions,
+    #[error(
+        "`{opcode}` at {offset:#X} in function `{function}` runs past the end of the function"
+    )]
+    TruncatedInstruction {
+        function: String,
+        offset: usize,
+        opcode: String,
+    },
+    #[error("Function name `{0}` is longer than 32 bytes")]
+    NameTooLong(String),
+    #[error("Line {line}: {message}")]
+    Assemble { line: usize, message: String },
+    #[error("`{file}` line {line}: {message}")]
+    Source {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    #[error("Patch failed: {0}")]
+    Patch(String),
+    #[error("Invalid script document: {0}")]
+    Document(String),
+    #[error("Transplant failed: {0}")]
+    Transplant(String),
+}
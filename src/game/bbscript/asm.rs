@@ -0,0 +1,374 @@
+//! Assembles the text form written by the disassembler back into a script buffer.
+//!
+//! One instruction per line, `mnemonic arg, arg, ...`. Arguments are integers
+//! (`12`, `-1`, `0x1F`), quoted strings (`"NmlAtk5A"`) or raw hex (`h"00ff"`).
+//! `.bytes h"..."` emits raw bytes and `#` starts a comment.
+//! Every state or subroutine start begins a new entry in the function table,
+//! `.alias "Name"` right before one adds another entry pointing at the same code.
+
+use super::disasm::Arg;
+use super::opcodes::{ArgType, OpcodeInfo, OpcodeKind, OpcodeTable};
+use super::{build_script, write_fixed_str, ScriptFunction, FUNCTION_NAME_SIZE};
+use crate::error::ScriptError;
+
+/// Pseudo instruction for bytes the opcode table can't describe
+const RAW_BYTES: &str = ".bytes";
+/// Pseudo instruction for an extra function table entry sharing the next function's code
+pub(crate) const ALIAS: &str = ".alias";
+
+pub fn assemble(source: &str, table: &OpcodeTable) -> Result<Vec<u8>, ScriptError> {
+    build_script(&assemble_functions(source, table)?)
+}
+
+/// Assembles source into separate functions without laying them out into a script
+pub fn assemble_functions(
+    source: &str,
+    table: &OpcodeTable,
+) -> Result<Vec<ScriptFunction>, ScriptError> {
+    assemble_lines(
+        source
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line)),
+        table,
+    )
+}
+
+/// Same as `assemble_functions` but for lines picked out of a larger file, errors use the given line numbers
+pub(crate) fn assemble_lines<'s>(
+    lines: impl IntoIterator<Item = (usize, &'s str)>,
+    table: &OpcodeTable,
+) -> Result<Vec<ScriptFunction>, ScriptError> {
+    let mut functions: Vec<ScriptFunction> = Vec::new();
+    // (line number, name) of aliases waiting for the function they belong to
+    let mut aliases: Vec<(usize, String)> = Vec::new();
+    let misplaced_alias = |aliases: &[(usize, String)]| match aliases.first() {
+        Some((line, _)) => Err(ScriptError::Assemble {
+            line: *line,
+            message: format!("`{}` has to come right before a state or subroutine", ALIAS),
+        }),
+        None => Ok(()),
+    };
+
+    for (line_number, line) in lines {
+        let error = |message: String| ScriptError::Assemble {
+            line: line_number,
+            message,
+        };
+
+        let (mnemonic, args) = match parse_line(line).map_err(error)? {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        if mnemonic == ALIAS {
+            match args.as_slice() {
+                [Arg::Str(name)] => aliases.push((line_number, name.clone())),
+                _ => return Err(error(format!("`{}` takes a single string argument", ALIAS))),
+            }
+            continue;
+        }
+
+        let bytes = if mnemonic == RAW_BYTES {
+            match args.as_slice() {
+                [Arg::Bytes(bytes)] => bytes.clone(),
+                _ => {
+                    return Err(error(format!(
+                        "`{}` takes a single h\"...\" argument",
+                        RAW_BYTES
+                    )))
+                }
+            }
+        } else {
+            let opcode = table
+                .by_name(mnemonic)
+                .ok_or_else(|| error(format!("Unknown instruction `{}`", mnemonic)))?;
+            let bytes = encode_instruction(opcode, &args).map_err(error)?;
+
+            if begins_function(opcode.kind) {
+                let name = match &args[0] {
+                    Arg::Str(name) => name.clone(),
+                    _ => {
+                        return Err(error(format!(
+                            "`{}` needs a plain string name",
+                            opcode.name
+                        )))
+                    }
+                };
+
+                // an alias without code of its own starts where the next function does
+                functions.extend(aliases.drain(..).map(|(_, name)| ScriptFunction {
+                    name,
+                    body: Vec::new(),
+                }));
+                functions.push(ScriptFunction {
+                    name,
+                    body: Vec::new(),
+                });
+            }
+
+            bytes
+        };
+
+        misplaced_alias(&aliases)?;
+
+        match functions.last_mut() {
+            Some(function) => function.body.extend_from_slice(&bytes),
+            None => return Err(error("Instruction outside of a state or subroutine".into())),
+        }
+    }
+
+    misplaced_alias(&aliases)?;
+
+    Ok(functions)
+}
+
+/// Encodes a single instruction, checking the arguments against the opcode layout
+pub fn encode_instruction(opcode: &OpcodeInfo, args: &[Arg]) -> Result<Vec<u8>, String> {
+    if args.len() != opcode.args.len() {
+        return Err(format!(
+            "`{}` takes {} arguments, got {}",
+            opcode.name,
+            opcode.args.len(),
+            args.len()
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity(opcode.size());
+    bytes.extend_from_slice(&opcode.id.to_le_bytes());
+
+    for (info, arg) in opcode.args.iter().zip(args) {
+        match (info.ty, arg) {
+            (ArgType::Int, Arg::Int(value)) => bytes.extend_from_slice(&value.to_le_bytes()),
+            (ArgType::String32, Arg::Str(value)) => {
+                let field = write_fixed_str(value).map_err(|e| e.to_string())?;
+                bytes.extend_from_slice(&field);
+            }
+            (ArgType::String32, Arg::Bytes(value)) if value.len() == FUNCTION_NAME_SIZE => {
+                bytes.extend_from_slice(value)
+            }
+            (ty, _) => {
+                return Err(format!(
+                    "Argument `{}` of `{}` must be {}",
+                    info.name,
+                    opcode.name,
+                    match ty {
+                        ArgType::Int => "an integer",
+                        ArgType::String32 => "a string of at most 32 bytes",
+                    }
+                ))
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+pub(crate) fn begins_function(kind: OpcodeKind) -> bool {
+    kind == OpcodeKind::BeginState || kind == OpcodeKind::BeginSubroutine
+}
+
+/// Splits a line into its mnemonic and arguments, `None` for blank and comment lines
+pub(crate) fn parse_line(line: &str) -> Result<Option<(&str, Vec<Arg>)>, String> {
+    let line = strip_comment(line).trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let (mnemonic, rest) = match line.find(char::is_whitespace) {
+        Some(split) => (&line[..split], line[split..].trim()),
+        None => (line, ""),
+    };
+
+    let args = split_args(rest)
+        .into_iter()
+        .map(parse_arg)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some((mnemonic, args)))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn split_args(args: &str) -> Vec<&str> {
+    if args.is_empty() {
+        return Vec::new();
+    }
+
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in args.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                split.push(args[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(args[start..].trim());
+
+    split
+}
+
+pub(crate) fn parse_arg(arg: &str) -> Result<Arg, String> {
+    if let Some(hex) = arg.strip_prefix("h\"") {
+        let hex = hex
+            .strip_suffix('"')
+            .ok_or_else(|| format!("Unterminated hex string `{}`", arg))?;
+        return parse_hex(hex).map(Arg::Bytes);
+    }
+
+    if let Some(quoted) = arg.strip_prefix('"') {
+        let quoted = quoted
+            .strip_suffix('"')
+            .ok_or_else(|| format!("Unterminated string `{}`", arg))?;
+        return unescape(quoted).map(Arg::Str);
+    }
+
+    parse_int(arg)
+        .map(Arg::Int)
+        .ok_or_else(|| format!("Invalid argument `{}`", arg))
+}
+
+/// Parses decimal or `0x` prefixed hex, hex values above `i32::MAX` wrap like they do in the game
+pub(crate) fn parse_int(value: &str) -> Option<i32> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    let value = if negative { -magnitude } else { magnitude };
+
+    if value >= i32::MIN as i64 && value <= u32::MAX as i64 {
+        Some(value as u32 as i32)
+    } else {
+        None
+    }
+}
+
+fn unescape(quoted: &str) -> Result<String, String> {
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ '\\') | Some(escaped @ '"') => value.push(escaped),
+                _ => return Err(format!("Invalid escape in string `\"{}\"`", quoted)),
+            },
+            '"' => return Err(format!("Unescaped quote in string `\"{}\"`", quoted)),
+            c => value.push(c),
+        }
+    }
+
+    Ok(value)
+}
+
+pub(crate) fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.is_ascii() {
+        return Err(format!("Invalid hex string `{}`", hex));
+    }
+    if hex.len() % 2 != 0 {
+        return Err(format!("Odd number of digits in hex string `{}`", hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| format!("Invalid hex string `{}`", hex))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("  # just a comment").unwrap(), None);
+        assert_eq!(
+            parse_line("sprite \"a, \\\"b\\\" # c\", -0x10 # comment").unwrap(),
+            Some((
+                "sprite",
+                vec![Arg::Str("a, \"b\" # c".into()), Arg::Int(-16)]
+            ))
+        );
+        assert_eq!(
+            parse_line(".bytes h\"00ff\"").unwrap(),
+            Some((".bytes", vec![Arg::Bytes(vec![0x00, 0xFF])]))
+        );
+        assert_eq!(parse_int("0xFFFFFFFF"), Some(-1));
+        assert!(parse_line("damage 1x").is_err());
+        assert!(parse_line("sprite \"open").is_err());
+    }
+
+    #[test]
+    fn assembles_functions() {
+        let table = OpcodeTable::builtin();
+        let functions = assemble_functions(
+            "startState \"NmlAtk5A\"\ndamage 30\nendState\nbeginSubroutine \"Sub\"\nendSubroutine\n",
+            &table,
+        )
+        .unwrap();
+
+        let mut damage = 26u32.to_le_bytes().to_vec();
+        damage.extend_from_slice(&30u32.to_le_bytes());
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].body[0x24..0x2C], damage[..]);
+        assert_eq!(functions[1].name, "Sub");
+
+        let error = assemble("startState \"A\"\nnoSuchOpcode\n", &table).unwrap_err();
+        assert!(matches!(error, ScriptError::Assemble { line: 2, .. }));
+        assert!(assemble("damage 30\n", &table).is_err());
+    }
+
+    #[test]
+    fn assembles_aliases() {
+        let table = OpcodeTable::builtin();
+        let functions =
+            assemble_functions(".alias \"B\"\nstartState \"A\"\nendState\n", &table).unwrap();
+
+        assert_eq!(functions.len(), 2);
+        assert_eq!(
+            (functions[0].name.as_str(), functions[0].body.len()),
+            ("B", 0)
+        );
+        assert_eq!(functions[1].name, "A");
+
+        let error = assemble("startState \"A\"\n.alias \"B\"\nendState\n", &table).unwrap_err();
+        assert!(matches!(error, ScriptError::Assemble { line: 2, .. }));
+        assert!(assemble("startState \"A\"\nendState\n.alias \"B\"\n", &table).is_err());
+        assert!(assemble(".alias 1\nstartState \"A\"\nendState\n", &table).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Turns functions into indented pseudo-code with the if/else/upon nesting rebuilt.
+//!
+//! ```text
+//! state NmlAtk5A {
+//!     sprite("sol201_00", 4)
+//!     upon Landing {
+//!         enterState("CmnActStand")
+//!     }
+//! }
+//! ```
+//!
+//! Conditions and other instructions are written with the `format` templates and
+//! enum names from the opcode table where it has them.
+
+use super::disasm::{indent, instructions, opens_block, Arg, Instruction, Item};
+use super::opcodes::{OpcodeKind, OpcodeTable};
+use super::{BBScript, FunctionEntry};
+
+use std::fmt::Write;
+
+/// Decompiles every function in table order
+pub fn decompile(script: &BBScript, table: &OpcodeTable) -> String {
+    script
+        .functions()
+        .iter()
+        .map(|function| decompile_function(script, function, table))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn decompile_function(
+    script: &BBScript,
+    function: &FunctionEntry,
+    table: &OpcodeTable,
+) -> String {
+    let mut out = String::new();
+    let mut depth = 0usize;
+
+    for item in instructions(script.function_body(function), table) {
+        let instruction = match item {
+            Item::Instruction(instruction) => instruction,
+            other => {
+                let _ = writeln!(
+                    out,
+                    "{}// {:#X} bytes the opcode table can't decode",
+                    indent(depth),
+                    script.function_body(function).len() - other.offset()
+                );
+                break;
+            }
+        };
+
+        let opcode = instruction.opcode;
+        let name = |index: usize| {
+            instruction
+                .args
+                .get(index)
+                .map(|arg| match arg {
+                    Arg::Str(name) => name.clone(),
+                    other => other.to_string(),
+                })
+                .unwrap_or_default()
+        };
+
+        match opcode.kind {
+            OpcodeKind::BeginState => {
+                let _ = writeln!(out, "{}state {} {{", indent(depth), name(0));
+                depth += 1;
+            }
+            OpcodeKind::BeginSubroutine => {
+                let _ = writeln!(out, "{}subroutine {} {{", indent(depth), name(0));
+                depth += 1;
+            }
+            OpcodeKind::BeginIf => {
+                let _ = writeln!(
+                    out,
+                    "{}if {} {{",
+                    indent(depth),
+                    render(&instruction, table)
+                );
+                depth += 1;
+            }
+            OpcodeKind::BeginUpon => {
+                let _ = writeln!(
+                    out,
+                    "{}upon {} {{",
+                    indent(depth),
+                    render(&instruction, table)
+                );
+                depth += 1;
+            }
+            OpcodeKind::Else => {
+                let _ = writeln!(out, "{}}} else {{", indent(depth.saturating_sub(1)));
+            }
+            OpcodeKind::EndState
+            | OpcodeKind::EndSubroutine
+            | OpcodeKind::EndIf
+            | OpcodeKind::EndUpon => {
+                depth = depth.saturating_sub(1);
+                let _ = writeln!(out, "{}}}", indent(depth));
+            }
+            // anything that doesn't change the nesting is a plain line
+            _ => {
+                let _ = writeln!(out, "{}{}", indent(depth), render(&instruction, table));
+            }
+        }
+    }
+
+    out
+}
+
+/// Fills in the opcode's format template, or writes it as a call if it doesn't have one
+fn render(instruction: &Instruction, table: &OpcodeTable) -> String {
+    let opcode = instruction.opcode;
+    let args = opcode
+        .args
+        .iter()
+        .zip(&instruction.args)
+        .map(|(info, arg)| {
+            let named = match (&info.enum_name, arg) {
+                (Some(enum_name), Arg::Int(value)) => table.enum_value(enum_name, *value),
+                _ => None,
+            };
+
+            (
+                info,
+                named.map(str::to_string).unwrap_or_else(|| arg.to_string()),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    match &opcode.format {
+        Some(format) => args.iter().fold(format.clone(), |text, (info, arg)| {
+            text.replace(&format!("{{{}}}", info.name), arg)
+        }),
+        None if !opens_block(opcode.kind) => format!(
+            "{}({})",
+            opcode.name,
+            args.iter()
+                .map(|(_, arg)| arg.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        // blocks without a template still need something to show as the condition
+        None => format!(
+            "{}({})",
+            opcode.name,
+            args.iter()
+                .map(|(info, arg)| format!("{}: {}", info.name, arg))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    #[test]
+    fn rebuilds_nesting() {
+        let table = OpcodeTable::builtin();
+        let script = assemble(
+            "startState \"NmlAtk5A\"\nsprite \"sol201_00\", 4\nifOperation 10, 2, 5, 0, 3\nupon 2\nenterState \"CmnActStand\"\nendUpon\nelse\nifNot 2, 7\ndamage 30\nendIf\nendIf\nupon 99\nendUpon\nendState\nbeginSubroutine \"Sub\"\nendSubroutine\n",
+            &table,
+        )
+        .unwrap();
+
+        assert_eq!(
+            decompile(&BBScript::parse(&script).unwrap(), &table),
+            "state NmlAtk5A {
+    sprite(\"sol201_00\", 4)
+    if Gt(Var(5), Literal(3)) {
+        upon Landing {
+            enterState(\"CmnActStand\")
+        }
+    } else {
+        if !Var(7) {
+            damage(30)
+        }
+    }
+    upon 99 {
+    }
+}
+
+subroutine Sub {
+}
+"
+        );
+    }
+
+    #[test]
+    fn stops_at_undecodable_bytes() {
+        let table = OpcodeTable::builtin();
+        let script = assemble(
+            "startState \"A\"\n.bytes h\"ffffffff0000\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+
+        assert_eq!(
+            decompile(&BBScript::parse(&script).unwrap(), &table),
+            "state A {\n    // 0xA bytes the opcode table can't decode\n"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Walks function bodies instruction by instruction and turns them into readable text.
+//!
+//! Anything the opcode table doesn't cover is kept as a raw byte blob, so the
+//! output always accounts for every byte of the script.
+
+use super::asm::ALIAS;
+use super::opcodes::{ArgType, OpcodeInfo, OpcodeKind, OpcodeTable, OPCODE_SIZE};
+use super::{read_u32, BBScript};
+
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Str(String),
+    /// String field that can't be shown as text without losing bytes
+    Bytes(Vec<u8>),
+}
+
+impl Arg {
+    fn decode(ty: ArgType, field: &[u8]) -> Self {
+        match ty {
+            ArgType::Int => Arg::Int(read_u32(field, 0).unwrap_or_default() as i32),
+            ArgType::String32 => {
+                let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+                let (text, padding) = field.split_at(len);
+
+                let printable = text.iter().all(|&b| b == b' ' || b.is_ascii_graphic());
+                if printable && padding.iter().all(|&b| b == 0) {
+                    Arg::Str(String::from_utf8_lossy(text).into_owned())
+                } else {
+                    Arg::Bytes(field.to_vec())
+                }
+            }
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            Arg::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Int(value) => write!(f, "{}", value),
+            Arg::Str(value) => write!(
+                f,
+                "\"{}\"",
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            Arg::Bytes(bytes) => write!(f, "h\"{}\"", hex(bytes)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction<'t> {
+    /// Offset from the start of the function body
+    pub offset: usize,
+    pub opcode: &'t OpcodeInfo,
+    pub args: Vec<Arg>,
+}
+
+impl<'t> fmt::Display for Instruction<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode.name)?;
+
+        for (index, arg) in self.args.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, arg)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Item<'t, 'a> {
+    Instruction(Instruction<'t>),
+    /// Opcode the table doesn't know about, the rest of the function can't be decoded
+    Unknown {
+        offset: usize,
+        bytes: &'a [u8],
+    },
+    /// Known opcode that runs past the end of the function
+    Truncated {
+        offset: usize,
+        opcode: &'t OpcodeInfo,
+        bytes: &'a [u8],
+    },
+}
+
+impl<'t, 'a> Item<'t, 'a> {
+    pub fn offset(&self) -> usize {
+        match self {
+            Item::Instruction(instruction) => instruction.offset,
+            Item::Unknown { offset, .. } | Item::Truncated { offset, .. } => *offset,
+        }
+    }
+}
+
+/// Iterator over the instructions in a function body
+pub struct Instructions<'t, 'a> {
+    body: &'a [u8],
+    position: usize,
+    table: &'t OpcodeTable,
+}
+
+pub fn instructions<'t, 'a>(body: &'a [u8], table: &'t OpcodeTable) -> Instructions<'t, 'a> {
+    Instructions {
+        body,
+        position: 0,
+        table,
+    }
+}
+
+impl<'t, 'a> Iterator for Instructions<'t, 'a> {
+    type Item = Item<'t, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.position;
+        let rest = &self.body[offset..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        // nothing after an undecodable instruction can be trusted
+        self.position = self.body.len();
+
+        let opcode = match read_u32(rest, 0).and_then(|id| self.table.get(id)) {
+            Some(opcode) => opcode,
+            None => {
+                return Some(Item::Unknown {
+                    offset,
+                    bytes: rest,
+                })
+            }
+        };
+
+        if rest.len() < opcode.size() {
+            return Some(Item::Truncated {
+                offset,
+                opcode,
+                bytes: rest,
+            });
+        }
+
+        let mut field_start = OPCODE_SIZE;
+        let args = opcode
+            .args
+            .iter()
+            .map(|arg| {
+                let field = &rest[field_start..field_start + arg.ty.size()];
+                field_start += arg.ty.size();
+                Arg::decode(arg.ty, field)
+            })
+            .collect();
+
+        self.position = offset + opcode.size();
+
+        Some(Item::Instruction(Instruction {
+            offset,
+            opcode,
+            args,
+        }))
+    }
+}
+
+/// Disassembles every function in code order into text the assembler can read back.
+/// Table entries sharing code with another function are written as `.alias` lines, they
+/// come back in front of that function in the table.
+pub fn disassemble(script: &BBScript, table: &OpcodeTable) -> String {
+    let mut out = String::new();
+
+    let mut functions = script.functions().iter().collect::<Vec<_>>();
+    functions.sort_by_key(|f| f.offset);
+
+    for (index, first) in functions.iter().enumerate() {
+        if index > 0 && functions[index - 1].offset == first.offset {
+            continue;
+        }
+
+        let entries = functions[index..]
+            .iter()
+            .take_while(|f| f.offset == first.offset)
+            .collect::<Vec<_>>();
+
+        // the assembler names the function after its first instruction, every other entry is an alias
+        let own_name = match instructions(script.function_body(first), table).next() {
+            Some(Item::Instruction(instruction)) => instruction
+                .args
+                .first()
+                .and_then(Arg::as_str)
+                .filter(|name| entries.iter().any(|f| f.name == *name))
+                .map(str::to_string),
+            _ => None,
+        };
+        let function = entries
+            .iter()
+            .find(|f| Some(&f.name) == own_name.as_ref())
+            .unwrap_or(&entries[0]);
+
+        let _ = writeln!(
+            out,
+            "# {} (offset {:#X}, length {:#X})",
+            function.name, function.offset, function.length
+        );
+
+        for alias in entries.iter().filter(|f| f.name != function.name) {
+            let _ = writeln!(out, "{} {}", ALIAS, Arg::Str(alias.name.clone()));
+        }
+
+        let mut depth = 0usize;
+        for item in instructions(script.function_body(function), table) {
+            match item {
+                Item::Instruction(instruction) => {
+                    let kind = instruction.opcode.kind;
+                    if closes_block(kind) {
+                        depth = depth.saturating_sub(1);
+                    }
+
+                    // else sits at the same level as the if it belongs to
+                    let line_depth = match kind {
+                        OpcodeKind::Else => depth.saturating_sub(1),
+                        _ => depth,
+                    };
+                    let _ = writeln!(out, "{}{}", indent(line_depth), instruction);
+
+                    if opens_block(kind) {
+                        depth += 1;
+                    }
+                }
+                Item::Unknown { offset, bytes } => {
+                    let _ = writeln!(out, "{}# unknown opcode at {:#X}", indent(depth), offset);
+                    let _ = writeln!(out, "{}.bytes h\"{}\"", indent(depth), hex(bytes));
+                }
+                Item::Truncated {
+                    offset,
+                    opcode,
+                    bytes,
+                } => {
+                    let _ = writeln!(
+                        out,
+                        "{}# truncated `{}` at {:#X}",
+                        indent(depth),
+                        opcode.name,
+                        offset
+                    );
+                    let _ = writeln!(out, "{}.bytes h\"{}\"", indent(depth), hex(bytes));
+                }
+            }
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+pub(crate) fn opens_block(kind: OpcodeKind) -> bool {
+    matches!(
+        kind,
+        OpcodeKind::BeginState
+            | OpcodeKind::BeginSubroutine
+            | OpcodeKind::BeginIf
+            | OpcodeKind::BeginUpon
+    )
+}
+
+pub(crate) fn closes_block(kind: OpcodeKind) -> bool {
+    matches!(
+        kind,
+        OpcodeKind::EndState | OpcodeKind::EndSubroutine | OpcodeKind::EndIf | OpcodeKind::EndUpon
+    )
+}
+
+pub(crate) fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+    use crate::game::bbscript::{build_script, write_fixed_str, ScriptFunction};
+
+    /// `startState name`, `sprite "sol201_00", 4`, `endState`
+    fn body(name: &str) -> Vec<u8> {
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&write_fixed_str(name).unwrap());
+        body.extend_from_slice(&2u32.to_le_bytes());
+        body.extend_from_slice(&write_fixed_str("sol201_00").unwrap());
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn decodes_instructions() {
+        let table = OpcodeTable::builtin();
+        let body = body("NmlAtk5A");
+
+        let decoded = instructions(&body, &table)
+            .map(|item| match item {
+                Item::Instruction(instruction) => (instruction.offset, instruction.to_string()),
+                other => panic!("Couldn't decode {:?}", other),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            decoded,
+            vec![
+                (0x0, "startState \"NmlAtk5A\"".to_string()),
+                (0x24, "sprite \"sol201_00\", 4".to_string()),
+                (0x4C, "endState".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_undecodable_bytes() {
+        let table = OpcodeTable::builtin();
+
+        let mut body = body("NmlAtk5A");
+        body.truncate(0x30);
+        match instructions(&body, &table).nth(1) {
+            Some(Item::Truncated { offset, bytes, .. }) => {
+                assert_eq!((offset, bytes.len()), (0x24, 0xC))
+            }
+            other => panic!("Expected a truncated instruction, got {:?}", other),
+        }
+
+        let body = 0xFFFF_FFFFu32.to_le_bytes();
+        match instructions(&body, &table).next() {
+            Some(Item::Unknown { offset: 0, bytes }) => assert_eq!(bytes, &body[..]),
+            other => panic!("Expected an unknown opcode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        let table = OpcodeTable::builtin();
+        let mut unknown = body("NmlAtk5B");
+        unknown.extend_from_slice(&[0xFF; 6]);
+
+        // an entry without code of its own shares `NmlAtk5A`'s
+        let data = build_script(&[
+            ScriptFunction {
+                name: "NmlAtk5A_Alias".into(),
+                body: Vec::new(),
+            },
+            ScriptFunction {
+                name: "NmlAtk5A".into(),
+                body: body("NmlAtk5A"),
+            },
+            ScriptFunction {
+                name: "NmlAtk5B".into(),
+                body: unknown,
+            },
+        ])
+        .unwrap();
+        let text = disassemble(&BBScript::parse(&data).unwrap(), &table);
+
+        assert!(text.contains("    sprite \"sol201_00\", 4\n"));
+        assert!(text.contains(".alias \"NmlAtk5A_Alias\"\nstartState \"NmlAtk5A\"\n"));
+        assert_eq!(assemble(&text, &table).unwrap(), data);
+    }
+}
@@ -0,0 +1,368 @@
+//! Structured JSON/YAML form of a script that converts back to the exact same bytes.
+//!
+//! ```json
+//! {
+//!   "functions": [
+//!     {
+//!       "name": "NmlAtk5A",
+//!       "instructions": [
+//!         { "op": "startState", "args": ["NmlAtk5A"] },
+//!         { "op": "sprite", "args": ["sol201_00", 4] },
+//!         { "bytes": "39300000" }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Functions are listed in code order. Anything the opcode table can't decode is
+//! kept as `bytes`, and the few things about the layout that can't be derived from
+//! the function list (code before the first function, function table order,
+//! several names sharing one function and name fields with bytes after the null
+//! or invalid UTF-8, which are kept as `hex`) get their own optional fields, so
+//! any script that parses survives the round trip.
+
+use super::asm::{encode_instruction, parse_hex};
+use super::disasm::{self, hex, instructions, Item};
+use super::opcodes::OpcodeTable;
+use super::{
+    read_fixed_str, write_fixed_str, BBScript, FUNCTION_ENTRY_SIZE, FUNCTION_NAME_SIZE, HEADER_SIZE,
+};
+use crate::error::ScriptError;
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptDocument {
+    /// Code before the first function
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preamble: Vec<Element>,
+    pub functions: Vec<FunctionDocument>,
+    /// Function table as `[name, index into functions]`, only there when it isn't
+    /// simply every function in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<Vec<(TableName, usize)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TableName {
+    Name(String),
+    /// Whole name field that doesn't survive as a string, hex encoded
+    Bytes {
+        hex: String,
+    },
+}
+
+impl TableName {
+    fn from_field(field: &[u8]) -> Self {
+        let name = read_fixed_str(field);
+
+        match write_fixed_str(&name) {
+            Ok(canonical) if canonical[..] == *field => TableName::Name(name),
+            _ => TableName::Bytes { hex: hex(field) },
+        }
+    }
+
+    fn to_field(&self) -> Result<[u8; FUNCTION_NAME_SIZE], ScriptError> {
+        match self {
+            TableName::Name(name) => write_fixed_str(name),
+            TableName::Bytes { hex } => {
+                let bytes = parse_hex(hex).map_err(ScriptError::Document)?;
+                bytes.as_slice().try_into().map_err(|_| {
+                    ScriptError::Document(format!(
+                        "Table name `{}` is {} bytes instead of {}",
+                        hex,
+                        bytes.len(),
+                        FUNCTION_NAME_SIZE
+                    ))
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for TableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableName::Name(name) => write!(f, "{}", name),
+            TableName::Bytes { hex } => write!(f, "{}", hex),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDocument {
+    pub name: String,
+    pub instructions: Vec<Element>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Element {
+    Instruction {
+        op: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<DocumentArg>,
+    },
+    /// Hex encoded bytes copied as is
+    Bytes { bytes: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentArg {
+    Int(i32),
+    Str(String),
+    /// String field that isn't plain text, hex encoded
+    Bytes {
+        hex: String,
+    },
+}
+
+impl From<disasm::Arg> for DocumentArg {
+    fn from(arg: disasm::Arg) -> Self {
+        match arg {
+            disasm::Arg::Int(value) => DocumentArg::Int(value),
+            disasm::Arg::Str(value) => DocumentArg::Str(value),
+            disasm::Arg::Bytes(bytes) => DocumentArg::Bytes { hex: hex(&bytes) },
+        }
+    }
+}
+
+impl ScriptDocument {
+    pub fn from_script(script: &BBScript, table: &OpcodeTable) -> Self {
+        let code = script.code();
+
+        // every distinct offset starts a block that runs until the next one
+        let mut offsets = script
+            .functions()
+            .iter()
+            .map(|function| function.offset as usize)
+            .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let preamble_end = offsets.first().copied().unwrap_or(code.len());
+        let preamble = decode_elements(&code[..preamble_end], table);
+
+        let functions = offsets
+            .iter()
+            .enumerate()
+            .map(|(index, &start)| {
+                let end = offsets.get(index + 1).copied().unwrap_or(code.len());
+                // named after the first table entry pointing at it
+                let name = script
+                    .functions()
+                    .iter()
+                    .find(|function| function.offset as usize == start)
+                    .map(|function| function.name.clone())
+                    .unwrap_or_default();
+
+                FunctionDocument {
+                    name,
+                    instructions: decode_elements(&code[start..end], table),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let entries = script
+            .functions()
+            .iter()
+            .enumerate()
+            .map(|(entry, function)| {
+                let index = offsets
+                    .binary_search(&(function.offset as usize))
+                    .unwrap_or_default();
+                let name = script.raw_name(entry).map_or_else(
+                    || TableName::Name(function.name.clone()),
+                    TableName::from_field,
+                );
+                (name, index)
+            })
+            .collect::<Vec<_>>();
+
+        let simple = entries.len() == functions.len()
+            && entries.iter().enumerate().all(|(index, (name, function))| {
+                *function == index && *name == TableName::Name(functions[index].name.clone())
+            });
+
+        Self {
+            preamble,
+            functions,
+            table: if simple { None } else { Some(entries) },
+        }
+    }
+
+    pub fn to_bytes(&self, table: &OpcodeTable) -> Result<Vec<u8>, ScriptError> {
+        let mut code = encode_elements(&self.preamble, "preamble", table)?;
+
+        let mut offsets = Vec::with_capacity(self.functions.len());
+        for function in &self.functions {
+            offsets.push(code.len() as u32);
+            code.extend(encode_elements(
+                &function.instructions,
+                &function.name,
+                table,
+            )?);
+        }
+
+        let entries = match &self.table {
+            Some(entries) => entries.clone(),
+            None => self
+                .functions
+                .iter()
+                .enumerate()
+                .map(|(index, function)| (TableName::Name(function.name.clone()), index))
+                .collect(),
+        };
+
+        let mut script =
+            Vec::with_capacity(HEADER_SIZE + entries.len() * FUNCTION_ENTRY_SIZE + code.len());
+        script.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+
+        for (name, index) in &entries {
+            let offset = offsets.get(*index).ok_or_else(|| {
+                ScriptError::Document(format!(
+                    "Table entry `{}` points at function {} but there are only {}",
+                    name,
+                    index,
+                    offsets.len()
+                ))
+            })?;
+
+            script.extend_from_slice(&name.to_field()?);
+            script.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        script.extend(code);
+        Ok(script)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ScriptError> {
+        serde_json::from_str(json).map_err(|e| ScriptError::Document(e.to_string()))
+    }
+
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, ScriptError> {
+        serde_yaml::from_str(yaml).map_err(|e| ScriptError::Document(e.to_string()))
+    }
+}
+
+/// Builds the script buffer described by a JSON document
+pub fn json_to_script(json: &str, table: &OpcodeTable) -> Result<Vec<u8>, ScriptError> {
+    ScriptDocument::from_json(json)?.to_bytes(table)
+}
+
+/// Builds the script buffer described by a YAML document
+pub fn yaml_to_script(yaml: &str, table: &OpcodeTable) -> Result<Vec<u8>, ScriptError> {
+    ScriptDocument::from_yaml(yaml)?.to_bytes(table)
+}
+
+fn decode_elements(body: &[u8], table: &OpcodeTable) -> Vec<Element> {
+    instructions(body, table)
+        .map(|item| match item {
+            Item::Instruction(instruction) => Element::Instruction {
+                op: instruction.opcode.name.clone(),
+                args: instruction
+                    .args
+                    .into_iter()
+                    .map(DocumentArg::from)
+                    .collect(),
+            },
+            Item::Unknown { bytes, .. } | Item::Truncated { bytes, .. } => {
+                Element::Bytes { bytes: hex(bytes) }
+            }
+        })
+        .collect()
+}
+
+fn encode_elements(
+    elements: &[Element],
+    function: &str,
+    table: &OpcodeTable,
+) -> Result<Vec<u8>, ScriptError> {
+    let error = |index: usize, message: String| {
+        ScriptError::Document(format!("`{}` instruction {}: {}", function, index, message))
+    };
+
+    let mut code = Vec::new();
+    for (index, element) in elements.iter().enumerate() {
+        match element {
+            Element::Instruction { op, args } => {
+                let opcode = table
+                    .by_name(op)
+                    .ok_or_else(|| error(index, format!("Unknown opcode `{}`", op)))?;
+
+                let args = args
+                    .iter()
+                    .map(|arg| {
+                        Ok(match arg {
+                            DocumentArg::Int(value) => disasm::Arg::Int(*value),
+                            DocumentArg::Str(value) => disasm::Arg::Str(value.clone()),
+                            DocumentArg::Bytes { hex } => disasm::Arg::Bytes(parse_hex(hex)?),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+                    .map_err(|e| error(index, e))?;
+
+                code.extend(encode_instruction(opcode, &args).map_err(|e| error(index, e))?);
+            }
+            Element::Bytes { bytes } => code.extend(parse_hex(bytes).map_err(|e| error(index, e))?),
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    fn sample(table: &OpcodeTable) -> Vec<u8> {
+        assemble(
+            "startState \"CharaInit\"\ncharacterName \"sol\"\nendState\nstartState \"NmlAtk5A\"\ndamage 100\nendState\n",
+            table,
+        )
+        .unwrap()
+    }
+
+    fn document(script: &[u8], table: &OpcodeTable) -> ScriptDocument {
+        ScriptDocument::from_script(&BBScript::parse(script).unwrap(), table)
+    }
+
+    #[test]
+    fn round_trip() {
+        let table = OpcodeTable::builtin();
+        let script = sample(&table);
+
+        let json = document(&script, &table).to_json().unwrap();
+        assert!(!json.contains("\"table\""));
+        assert_eq!(json_to_script(&json, &table).unwrap(), script);
+
+        let yaml = document(&script, &table).to_yaml().unwrap();
+        assert_eq!(yaml_to_script(&yaml, &table).unwrap(), script);
+    }
+
+    #[test]
+    fn round_trip_keeps_bytes_after_name() {
+        let table = OpcodeTable::builtin();
+        let mut script = sample(&table);
+        // byte after the null of the first table name
+        script[HEADER_SIZE + "CharaInit".len() + 1] = 0x41;
+
+        let json = document(&script, &table).to_json().unwrap();
+        assert!(json.contains("\"hex\""));
+        assert_eq!(json_to_script(&json, &table).unwrap(), script);
+    }
+}
@@ -0,0 +1,260 @@
+//! Startup, active and recovery frames of attack states, worked out without running the game.
+//!
+//! A state's timeline is the sum of its sprite durations. The frame after the
+//! first `HitActivate` instruction is the first active frame and the last
+//! `Recovery` instruction ends the active window. Only instructions directly in the state body are
+//! counted, states that also show sprites or hit inside `if` blocks are marked
+//! as conditional since their numbers depend on which branch runs. `upon`
+//! blocks are event handlers and aren't part of the timeline. States with bytes
+//! that can't be decoded are marked as incomplete, their numbers stop there.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{ArgRole, OpcodeKind, OpcodeTable};
+use super::strings::csv_field;
+use super::BBScript;
+
+use serde::Serialize;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameData {
+    pub state: String,
+    /// Frame number of the first active frame, matching how frame data is usually listed
+    pub startup: u32,
+    pub active: u32,
+    pub recovery: u32,
+    pub total: u32,
+    /// Number of hit activations in the state
+    pub hits: u32,
+    /// Some sprites or hit activations are inside `if` blocks
+    pub conditional: bool,
+    /// Decoding stopped early, only the part of the state before that is counted
+    pub incomplete: bool,
+}
+
+/// Frame data for every state that activates a hitbox, in code order
+pub fn frame_data(script: &BBScript, table: &OpcodeTable) -> Vec<FrameData> {
+    let mut functions = script.functions().iter().collect::<Vec<_>>();
+    functions.sort_by_key(|f| f.offset);
+    functions.dedup_by_key(|f| f.offset);
+
+    let mut states = Vec::new();
+
+    for function in functions {
+        let mut elapsed = 0u32;
+        let mut first_hit = None;
+        let mut active_end = None;
+        let mut hits = 0;
+        let mut conditional = false;
+        let mut incomplete = false;
+        let mut is_state = false;
+        // kinds of the blocks inside the state that are still open
+        let mut blocks = Vec::new();
+
+        for item in instructions(script.function_body(function), table) {
+            let instruction = match item {
+                Item::Instruction(instruction) => instruction,
+                _ => {
+                    incomplete = true;
+                    break;
+                }
+            };
+            let opcode = instruction.opcode;
+
+            if instruction.offset == 0 {
+                is_state = opcode.kind == OpcodeKind::BeginState;
+                continue;
+            }
+
+            match opcode.kind {
+                OpcodeKind::BeginIf | OpcodeKind::BeginUpon => {
+                    blocks.push(opcode.kind);
+                    continue;
+                }
+                OpcodeKind::EndIf | OpcodeKind::EndUpon => {
+                    blocks.pop();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let in_upon = blocks.contains(&OpcodeKind::BeginUpon);
+            let in_if = blocks.contains(&OpcodeKind::BeginIf);
+            if in_upon {
+                continue;
+            }
+
+            let duration = opcode
+                .arg_with_role(ArgRole::Duration)
+                .and_then(|index| instruction.args[index].as_int());
+            let is_hit = opcode.kind == OpcodeKind::HitActivate;
+            let is_recovery = opcode.kind == OpcodeKind::Recovery;
+
+            if in_if {
+                conditional |= duration.is_some() || is_hit || is_recovery;
+                continue;
+            }
+
+            if let Some(duration) = duration {
+                elapsed = elapsed.saturating_add(duration.max(0) as u32);
+            } else if is_hit {
+                hits += 1;
+                first_hit.get_or_insert(elapsed);
+                active_end = None;
+            } else if is_recovery && first_hit.is_some() {
+                active_end = Some(elapsed);
+            }
+        }
+
+        let first_hit = match first_hit {
+            Some(first_hit) if is_state => first_hit,
+            _ => continue,
+        };
+        // without a recovery marker the hitbox stays out until the state ends
+        let active_end = active_end.unwrap_or(elapsed);
+
+        states.push(FrameData {
+            state: function.name.clone(),
+            startup: first_hit.saturating_add(1),
+            active: active_end - first_hit,
+            recovery: elapsed - active_end,
+            total: elapsed,
+            hits,
+            conditional,
+            incomplete,
+        });
+    }
+
+    states
+}
+
+pub fn to_csv(states: &[FrameData]) -> String {
+    let mut csv = String::from("state,startup,active,recovery,total,hits,conditional,incomplete\n");
+
+    for state in states {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            csv_field(&state.state),
+            state.startup,
+            state.active,
+            state.recovery,
+            state.total,
+            state.hits,
+            state.conditional,
+            state.incomplete
+        );
+    }
+
+    csv
+}
+
+pub fn to_markdown(states: &[FrameData]) -> String {
+    let mut markdown = String::from(
+        "| State | Startup | Active | Recovery | Total | Hits |\n|---|---|---|---|---|---|\n",
+    );
+
+    for state in states {
+        let _ = writeln!(
+            markdown,
+            "| {}{}{} | {} | {} | {} | {} | {} |",
+            state.state,
+            if state.conditional { " *" } else { "" },
+            if state.incomplete { " ?" } else { "" },
+            state.startup,
+            state.active,
+            state.recovery,
+            state.total,
+            state.hits
+        );
+    }
+
+    if states.iter().any(|state| state.conditional) {
+        markdown.push_str(
+            "\n\\* depends on conditions in the script, numbers are for the unconditional path\n",
+        );
+    }
+
+    if states.iter().any(|state| state.incomplete) {
+        markdown.push_str(
+            "\n? part of the state couldn't be decoded, numbers only cover what comes before it\n",
+        );
+    }
+
+    markdown
+}
+
+pub fn to_json(states: &[FrameData]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    fn states(source: &str) -> Vec<FrameData> {
+        let table = OpcodeTable::builtin();
+        let script = assemble(source, &table).unwrap();
+        frame_data(&BBScript::parse(&script).unwrap(), &table)
+    }
+
+    fn numbers(state: &FrameData) -> (u32, u32, u32, u32, u32) {
+        (
+            state.startup,
+            state.active,
+            state.recovery,
+            state.total,
+            state.hits,
+        )
+    }
+
+    #[test]
+    fn sums_sprites() {
+        let states = states(
+            "startState \"A\"\nsprite \"a\", 4\nsprite \"b\", 2\nhitActivate\nsprite \"c\", 3\nrecoveryState\nsprite \"d\", 10\nendState\nstartState \"NoHit\"\nsprite \"a\", 4\nendState\n",
+        );
+
+        assert_eq!(states.len(), 1);
+        assert_eq!(numbers(&states[0]), (7, 3, 10, 19, 1));
+        assert!(!states[0].conditional && !states[0].incomplete);
+    }
+
+    #[test]
+    fn multiple_hits() {
+        let states = states(
+            "startState \"A\"\nsprite \"a\", 5\nhitActivate\nsprite \"b\", 2\nrecoveryState\nsprite \"c\", 1\nhitActivate\nsprite \"d\", 2\nrecoveryState\nsprite \"e\", 6\nendState\n",
+        );
+
+        // the active window runs from the first hit to the last recovery
+        assert_eq!(numbers(&states[0]), (6, 5, 6, 16, 2));
+    }
+
+    #[test]
+    fn skips_ifs_and_upons() {
+        let found = states(
+            "startState \"A\"\nsprite \"a\", 3\nupon 3\nsprite \"x\", 20\nhitActivate\nendUpon\nif 0, 1\nsprite \"y\", 20\nendIf\nhitActivate\nsprite \"b\", 2\nendState\n",
+        );
+
+        assert_eq!(numbers(&found[0]), (4, 2, 0, 5, 1));
+        assert!(found[0].conditional);
+        assert!(to_markdown(&found).contains("| A * |"));
+
+        let upon_only =
+            states("startState \"A\"\nsprite \"a\", 3\nupon 3\nhitActivate\nendUpon\nendState\n");
+        assert!(upon_only.is_empty());
+    }
+
+    #[test]
+    fn marks_undecodable_states() {
+        let states = states(
+            "startState \"A\"\nsprite \"a\", 3\nhitActivate\nsprite \"b\", 2\n.bytes h\"ffffffff\"\nsprite \"c\", 9\nendState\n",
+        );
+
+        assert_eq!(numbers(&states[0]), (4, 2, 0, 5, 1));
+        assert!(states[0].incomplete);
+        assert!(to_csv(&states).ends_with(",false,true\n"));
+        assert!(to_markdown(&states).contains("| A ? |"));
+        assert!(to_json(&states).unwrap().contains("\"incomplete\": true"));
+    }
+}
@@ -0,0 +1,382 @@
+//! Catches common authoring mistakes in scripts that still pass validation.
+//!
+//! Most states are entered by the engine by name rather than from another script,
+//! so whether a state is reachable can only be told for states the vanilla script
+//! doesn't have: the engine doesn't know about those, so unless something in the
+//! script or its `_ef` script enters them they never run. Subroutines get the same
+//! treatment, vanilla ones may be called from `cmn` or by the engine.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{ArgRole, OpcodeKind, OpcodeTable};
+use super::BBScript;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.function, self.message)
+    }
+}
+
+/// Lints a script, `effect` is its `_ef` script if there is one to check object references against.
+/// New states and subroutines are only checked for being unreachable when the `vanilla` script is given.
+pub fn lint(
+    script: &BBScript,
+    effect: Option<&BBScript>,
+    vanilla: Option<&BBScript>,
+    table: &OpcodeTable,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let mut definitions = HashMap::<&str, usize>::new();
+    for function in script.functions() {
+        *definitions.entry(&function.name).or_default() += 1;
+    }
+    for function in script.functions() {
+        if let Some(count) = definitions
+            .remove(function.name.as_str())
+            .filter(|&c| c > 1)
+        {
+            findings.push(Finding {
+                function: function.name.clone(),
+                message: format!("Defined {} times", count),
+            });
+        }
+    }
+
+    let mut called = HashSet::new();
+    let mut entered = HashSet::new();
+    // a reference could hide in anything that didn't decode, nothing can be called unreachable then
+    let mut refs_complete = true;
+    for checked in std::iter::once(script).chain(effect) {
+        for function in checked.functions() {
+            refs_complete &= for_each_ref(checked, function, table, |role, name| match role {
+                ArgRole::SubroutineRef => {
+                    called.insert(name.to_string());
+                }
+                ArgRole::StateRef => {
+                    entered.insert(name.to_string());
+                }
+                _ => {}
+            });
+        }
+    }
+
+    for function in script.functions() {
+        let mut finding = |message: String| {
+            findings.push(Finding {
+                function: function.name.clone(),
+                message,
+            })
+        };
+
+        // (opcode name, kind, offset) of every block that is still open
+        let mut open_blocks: Vec<(&str, OpcodeKind, usize)> = Vec::new();
+        let mut labels = HashSet::new();
+        let mut label_jumps = Vec::new();
+        let mut ended = false;
+        let mut complete = true;
+        let mut is_subroutine = false;
+        let mut is_state = false;
+
+        for item in instructions(script.function_body(function), table) {
+            let instruction = match item {
+                Item::Instruction(instruction) => instruction,
+                // validation already reports these, nothing after them can be checked
+                _ => {
+                    complete = false;
+                    break;
+                }
+            };
+
+            if ended {
+                finding(format!(
+                    "Unreachable instructions after the end of the function at {:#X}",
+                    instruction.offset
+                ));
+                break;
+            }
+
+            let opcode = instruction.opcode;
+            let offset = instruction.offset;
+
+            if instruction.offset == 0 {
+                is_subroutine = opcode.kind == OpcodeKind::BeginSubroutine;
+                is_state = opcode.kind == OpcodeKind::BeginState;
+            }
+
+            let opener = match opcode.kind {
+                OpcodeKind::EndState => Some(OpcodeKind::BeginState),
+                OpcodeKind::EndSubroutine => Some(OpcodeKind::BeginSubroutine),
+                OpcodeKind::EndIf | OpcodeKind::Else => Some(OpcodeKind::BeginIf),
+                OpcodeKind::EndUpon => Some(OpcodeKind::BeginUpon),
+                _ => None,
+            };
+
+            match opcode.kind {
+                OpcodeKind::BeginState | OpcodeKind::BeginSubroutine if !open_blocks.is_empty() => {
+                    finding(format!(
+                        "`{}` at {:#X} is inside another block",
+                        opcode.name, offset
+                    ));
+                    open_blocks.push((&opcode.name, opcode.kind, offset));
+                }
+                OpcodeKind::BeginState
+                | OpcodeKind::BeginSubroutine
+                | OpcodeKind::BeginIf
+                | OpcodeKind::BeginUpon => open_blocks.push((&opcode.name, opcode.kind, offset)),
+                OpcodeKind::Else => match open_blocks.last() {
+                    Some((_, kind, _)) if Some(*kind) == opener => {}
+                    _ => finding(format!(
+                        "`{}` at {:#X} is not inside an if",
+                        opcode.name, offset
+                    )),
+                },
+                _ => {}
+            }
+
+            match opener {
+                Some(opener) if opcode.kind != OpcodeKind::Else => {
+                    match open_blocks.pop() {
+                        Some((_, kind, _)) if kind == opener => {}
+                        Some((name, _, open_offset)) => finding(format!(
+                            "`{}` at {:#X} closes `{}` from {:#X}",
+                            opcode.name, offset, name, open_offset
+                        )),
+                        None => finding(format!(
+                            "`{}` at {:#X} has no block to close",
+                            opcode.name, offset
+                        )),
+                    }
+
+                    if open_blocks.is_empty()
+                        && (opcode.kind == OpcodeKind::EndState
+                            || opcode.kind == OpcodeKind::EndSubroutine)
+                    {
+                        ended = true;
+                    }
+                }
+                _ => {}
+            }
+
+            for (info, arg) in opcode.args.iter().zip(&instruction.args) {
+                match (info.role, arg.as_int(), arg.as_str()) {
+                    (Some(ArgRole::LabelDef), Some(label), _) => {
+                        labels.insert(label);
+                    }
+                    (Some(ArgRole::LabelRef), Some(label), _) => label_jumps.push((label, offset)),
+                    (Some(ArgRole::ObjectRef), _, Some(object))
+                        if effect.map_or(false, |effect| effect.function(object).is_none()) =>
+                    {
+                        finding(format!(
+                            "Creates object `{}` at {:#X} which isn't in the effect script",
+                            object, offset
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if !complete {
+            continue;
+        }
+
+        for (name, _, offset) in open_blocks {
+            finding(format!("`{}` at {:#X} is never closed", name, offset));
+        }
+
+        for (label, offset) in label_jumps {
+            if !labels.contains(&label) {
+                finding(format!(
+                    "Jump at {:#X} to label {} which doesn't exist",
+                    offset, label
+                ));
+            }
+        }
+
+        let is_new = vanilla.map_or(false, |vanilla| vanilla.function(&function.name).is_none());
+        if !is_new || !refs_complete {
+            continue;
+        }
+
+        if is_subroutine && !called.contains(&function.name) {
+            finding("New subroutine is never called".into());
+        }
+
+        if is_state && !entered.contains(&function.name) {
+            finding(
+                "New state is never entered, the game only enters states it knows by itself".into(),
+            );
+        }
+    }
+
+    findings
+}
+
+/// Calls `f` with the role and value of every string argument with a role in a function,
+/// returns false if part of the function couldn't be decoded
+fn for_each_ref(
+    script: &BBScript,
+    function: &super::FunctionEntry,
+    table: &OpcodeTable,
+    mut f: impl FnMut(ArgRole, &str),
+) -> bool {
+    for item in instructions(script.function_body(function), table) {
+        let instruction = match item {
+            Item::Instruction(instruction) => instruction,
+            _ => return false,
+        };
+
+        for (info, arg) in instruction.opcode.args.iter().zip(&instruction.args) {
+            if let (Some(role), Some(name)) = (info.role, arg.as_str()) {
+                f(role, name);
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    #[test]
+    fn new_states_must_be_entered() {
+        let table = OpcodeTable::builtin();
+        let vanilla = assemble("startState \"Old\"\nendState\n", &table).unwrap();
+        let modded = assemble(
+            "startState \"Old\"\nenterState \"Entered\"\nendState\nstartState \"Entered\"\nendState\nstartState \"Lost\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+        let (vanilla, modded) = (
+            BBScript::parse(&vanilla).unwrap(),
+            BBScript::parse(&modded).unwrap(),
+        );
+
+        let findings = lint(&modded, None, Some(&vanilla), &table);
+        let unreachable = findings
+            .iter()
+            .map(|finding| finding.function.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(unreachable, vec!["Lost"]);
+
+        assert!(lint(&modded, None, None, &table).is_empty());
+    }
+
+    /// Assembles and lints `source`, returns the findings as text
+    fn lint_source(source: &str, effect: Option<&str>, vanilla: Option<&str>) -> Vec<String> {
+        let table = OpcodeTable::builtin();
+        let assembled = |source: &str| assemble(source, &table).unwrap();
+        let (script, effect, vanilla) = (
+            assembled(source),
+            effect.map(assembled),
+            vanilla.map(assembled),
+        );
+        let effect = effect.as_deref().map(|data| BBScript::parse(data).unwrap());
+        let vanilla = vanilla
+            .as_deref()
+            .map(|data| BBScript::parse(data).unwrap());
+
+        lint(
+            &BBScript::parse(&script).unwrap(),
+            effect.as_ref(),
+            vanilla.as_ref(),
+            &table,
+        )
+        .iter()
+        .map(Finding::to_string)
+        .collect()
+    }
+
+    #[test]
+    fn new_subroutines_must_be_called() {
+        let vanilla = "startState \"A\"\nendState\nbeginSubroutine \"Old\"\nendSubroutine\n";
+        let modded = "startState \"A\"\ncallSubroutine \"Called\"\nendState\nbeginSubroutine \"Old\"\nendSubroutine\nbeginSubroutine \"Called\"\nendSubroutine\nbeginSubroutine \"Lost\"\nendSubroutine\n";
+
+        assert_eq!(
+            lint_source(modded, None, Some(vanilla)),
+            vec!["`Lost`: New subroutine is never called"]
+        );
+        assert!(lint_source(modded, None, None).is_empty());
+
+        // the call could be in the part of `A` that doesn't decode
+        let undecodable = modded.replace("endState", ".bytes h\"ffffffff\"\nendState");
+        assert_eq!(
+            lint_source(&undecodable, None, Some(vanilla)),
+            Vec::<String>::new()
+        );
+
+        // same for the effect script
+        let effect = "startState \"Obj\"\n.bytes h\"ffffffff\"\nendState\n";
+        assert!(lint_source(modded, Some(effect), Some(vanilla))
+            .iter()
+            .all(|finding| !finding.contains("never called")));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        assert_eq!(
+            lint_source(
+                "startState \"A\"\nendState\nstartState \"A\"\nendState\n",
+                None,
+                None
+            ),
+            vec!["`A`: Defined 2 times"]
+        );
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        assert_eq!(
+            lint_source("startState \"A\"\nif 0, 1\nendState\n", None, None),
+            vec![
+                "`A`: `endState` at 0x30 closes `if` from 0x24",
+                "`A`: `startState` at 0x0 is never closed",
+            ]
+        );
+        assert_eq!(
+            lint_source("startState \"A\"\nendState\nendIf\n", None, None),
+            vec!["`A`: Unreachable instructions after the end of the function at 0x28"]
+        );
+        assert_eq!(
+            lint_source("startState \"A\"\nelse\nendState\n", None, None),
+            vec!["`A`: `else` at 0x24 is not inside an if"]
+        );
+    }
+
+    #[test]
+    fn missing_labels() {
+        assert_eq!(
+            lint_source(
+                "startState \"A\"\nlabel 1\ngotoLabel 1\ngotoLabel 2\nendState\n",
+                None,
+                None
+            ),
+            vec!["`A`: Jump at 0x34 to label 2 which doesn't exist"]
+        );
+    }
+
+    #[test]
+    fn missing_effect_objects() {
+        let source =
+            "startState \"A\"\ncreateObject \"Shot\", 0\ncreateObject \"Gone\", 0\nendState\n";
+        let effect = "startState \"Shot\"\nendState\n";
+
+        assert_eq!(
+            lint_source(source, Some(effect), None),
+            vec!["`A`: Creates object `Gone` at 0x4C which isn't in the effect script"]
+        );
+        assert!(lint_source(source, None, None).is_empty());
+    }
+}
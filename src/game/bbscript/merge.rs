@@ -0,0 +1,165 @@
+//! Combines several mods for the same script at function granularity.
+//!
+//! Every source is turned into a patch against vanilla and the patches are
+//! applied in order. A function touched by more than one source is a conflict,
+//! the source applied last wins.
+
+use super::patch::{Patch, PatchOp};
+use super::{BBScript, ScriptFunction};
+use crate::error::ScriptError;
+
+#[derive(Debug, Clone)]
+pub struct MergeSource {
+    /// Used in conflict reports, usually the path of the mod file
+    pub name: String,
+    pub patch: Patch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub function: String,
+    /// Source whose change got overwritten
+    pub overridden: String,
+    /// Source whose change ended up in the script
+    pub winner: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub functions: Vec<ScriptFunction>,
+    pub conflicts: Vec<Conflict>,
+}
+
+pub fn merge(vanilla: &BBScript, sources: &[MergeSource]) -> Result<Merged, ScriptError> {
+    let mut functions = vanilla.to_functions();
+    let mut conflicts = Vec::new();
+    // (function name, index of the source that last touched it)
+    let mut touched: Vec<(String, usize)> = Vec::new();
+
+    for (source_index, source) in sources.iter().enumerate() {
+        for operation in &source.patch.operations {
+            let target = operation.target();
+
+            match touched.iter_mut().find(|(name, _)| name == target) {
+                Some((_, owner)) => {
+                    if *owner != source_index {
+                        conflicts.push(Conflict {
+                            function: target.to_string(),
+                            overridden: sources[*owner].name.clone(),
+                            winner: source.name.clone(),
+                        });
+                        *owner = source_index;
+                    }
+
+                    // whatever the earlier source did, this one gets the final say
+                    let position = functions.iter().position(|f| f.name == target);
+                    match (operation, position) {
+                        (PatchOp::Add(function), Some(index))
+                        | (PatchOp::Replace(function), Some(index)) => {
+                            functions[index] = function.clone()
+                        }
+                        (PatchOp::Add(function), None) | (PatchOp::Replace(function), None) => {
+                            functions.push(function.clone())
+                        }
+                        (PatchOp::Remove(_), Some(index)) => {
+                            functions.remove(index);
+                        }
+                        (PatchOp::Remove(_), None) => {}
+                    }
+                }
+                None => {
+                    touched.push((target.to_string(), source_index));
+
+                    Patch {
+                        operations: vec![operation.clone()],
+                    }
+                    .apply_to(&mut functions)
+                    .map_err(|e| match e {
+                        ScriptError::Patch(message) => {
+                            ScriptError::Patch(format!("{}: {}", source.name, message))
+                        }
+                        e => e,
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(Merged {
+        functions,
+        conflicts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::{assemble, assemble_functions};
+    use crate::game::bbscript::opcodes::OpcodeTable;
+
+    const VANILLA: &str =
+        "startState \"A\"\nendState\nstartState \"B\"\nendState\nstartState \"C\"\nendState\n";
+
+    fn source(name: &str, patch: &str) -> MergeSource {
+        MergeSource {
+            name: name.to_string(),
+            patch: Patch::parse(patch, &OpcodeTable::builtin()).unwrap(),
+        }
+    }
+
+    fn merge_sources(sources: &[MergeSource]) -> Result<Merged, ScriptError> {
+        let vanilla = assemble(VANILLA, &OpcodeTable::builtin()).unwrap();
+        merge(&BBScript::parse(&vanilla).unwrap(), sources)
+    }
+
+    #[test]
+    fn merges_sources() {
+        let merged = merge_sources(&[
+            source(
+                "one",
+                "@replace \"A\"\nstartState \"A\"\ndamage 1\nendState\n@remove \"B\"\n@add \"D\"\nstartState \"D\"\nendState\n",
+            ),
+            source(
+                "two",
+                "@replace \"A\"\nstartState \"A\"\ndamage 2\nendState\n@replace \"B\"\nstartState \"B\"\ndamage 2\nendState\n@add \"E\"\nstartState \"E\"\nendState\n",
+            ),
+        ])
+        .unwrap();
+
+        // `B` was removed by `one`, so `two`'s replacement ends up at the end
+        let expected = assemble_functions(
+            "startState \"A\"\ndamage 2\nendState\nstartState \"C\"\nendState\nstartState \"D\"\nendState\nstartState \"B\"\ndamage 2\nendState\nstartState \"E\"\nendState\n",
+            &OpcodeTable::builtin(),
+        )
+        .unwrap();
+        assert_eq!(merged.functions, expected);
+
+        let conflict = |function: &str| Conflict {
+            function: function.to_string(),
+            overridden: "one".to_string(),
+            winner: "two".to_string(),
+        };
+        assert_eq!(merged.conflicts, vec![conflict("A"), conflict("B")]);
+    }
+
+    #[test]
+    fn one_source_is_no_conflict() {
+        let merged = merge_sources(&[source(
+            "one",
+            "@remove \"B\"\n@add \"B\"\nstartState \"B\"\ndamage 1\nendState\n",
+        )])
+        .unwrap();
+
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.functions.last().unwrap().name, "B");
+    }
+
+    #[test]
+    fn errors_name_the_source() {
+        let error = merge_sources(&[source("bad.bbpatch", "@remove \"Missing\"\n")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            ScriptError::Patch("bad.bbpatch: no function named `Missing`".into()).to_string()
+        );
+    }
+}
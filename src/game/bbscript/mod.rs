@@ -0,0 +1,357 @@
+//! Parser for the BBScript container the game hands to `LoadBBScript`.
+//!
+//! Layout of a script buffer:
+//!
+//! ```text
+//! 0x00               u32             number of entries in the function table
+//! 0x04               [entry; count]  0x24 byte entries: 0x20 byte name + u32 code offset
+//! 0x04 + count*0x24  code            instruction stream, function offsets are relative to this
+//! ```
+//!
+//! Everything in here works on plain byte slices so it can be used on buffers
+//! read from disk as well as the ones the game passes to the hook.
+
+pub mod asm;
+pub mod decompile;
+pub mod disasm;
+pub mod document;
+pub mod framedata;
+pub mod lint;
+pub mod merge;
+pub mod opcodes;
+pub mod pair;
+pub mod patch;
+pub mod preprocess;
+pub mod search;
+pub mod strings;
+pub mod transplant;
+pub mod validate;
+pub mod xref;
+
+use crate::error::ScriptError;
+
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+/// Size of the function count at the start of the script
+pub const HEADER_SIZE: usize = 0x4;
+/// Size of a single function table entry
+pub const FUNCTION_ENTRY_SIZE: usize = 0x24;
+/// Size of the fixed width name field in a function table entry
+pub const FUNCTION_NAME_SIZE: usize = 0x20;
+
+// offset into the code section of the instruction holding the characters shortname,
+// it's the second instruction in the first function and the name is its first argument
+const CHARACTER_NAME_INSTRUCTION: usize = 0x24;
+const CHARACTER_NAME_OFFSET: usize = CHARACTER_NAME_INSTRUCTION + 0x4;
+/// Id of the instruction main scripts declare their character with, as listed in the built in
+/// opcode table. Kept separate so a table that renames it can't change which scripts count as
+/// main scripts. It hasn't been checked against every game version, so the hook also accepts
+/// a known shortname at `CHARACTER_NAME_OFFSET` on its own.
+const CHARACTER_NAME_OPCODE: u32 = 20;
+
+/// A single entry in the function table, offset and length are relative to the code section
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionEntry {
+    pub name: String,
+    pub offset: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BBScript<'a> {
+    functions: Vec<FunctionEntry>,
+    /// Function table as stored, `FunctionEntry::name` stops at the first null
+    table: &'a [u8],
+    code: &'a [u8],
+}
+
+impl<'a> BBScript<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ScriptError> {
+        let state_count = read_u32(data, 0).ok_or(ScriptError::MissingHeader(data.len()))?;
+
+        let code_start = (state_count as usize)
+            .checked_mul(FUNCTION_ENTRY_SIZE)
+            .and_then(|table_size| table_size.checked_add(HEADER_SIZE))
+            .filter(|&code_start| code_start <= data.len())
+            .ok_or(ScriptError::TableOutOfBounds {
+                count: state_count,
+                size: data.len(),
+            })?;
+
+        let table = &data[HEADER_SIZE..code_start];
+        let code = &data[code_start..];
+
+        let mut functions = Vec::with_capacity(state_count as usize);
+        for index in 0..state_count as usize {
+            let entry_start = HEADER_SIZE + index * FUNCTION_ENTRY_SIZE;
+            let entry = &data[entry_start..entry_start + FUNCTION_ENTRY_SIZE];
+
+            let name = read_fixed_str(&entry[..FUNCTION_NAME_SIZE]);
+            let offset = read_u32(entry, FUNCTION_NAME_SIZE).unwrap_or_default();
+
+            if offset as usize > code.len() {
+                return Err(ScriptError::FunctionOutOfBounds {
+                    name,
+                    offset,
+                    code_size: code.len(),
+                });
+            }
+
+            functions.push(FunctionEntry {
+                name,
+                offset,
+                length: 0,
+            });
+        }
+
+        // the table doesn't store lengths, a function runs until the next one starts
+        let mut offsets = functions.iter().map(|f| f.offset).collect::<Vec<_>>();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        for function in functions.iter_mut() {
+            let end = offsets
+                .iter()
+                .find(|&&offset| offset > function.offset)
+                .copied()
+                .unwrap_or(code.len() as u32);
+            function.length = end - function.offset;
+        }
+
+        Ok(Self {
+            functions,
+            table,
+            code,
+        })
+    }
+
+    /// Function table in the order it's stored in the script
+    pub fn functions(&self) -> &[FunctionEntry] {
+        &self.functions
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionEntry> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Name field of a function table entry as stored, including the padding after the name
+    pub fn raw_name(&self, index: usize) -> Option<&'a [u8]> {
+        let start = index.checked_mul(FUNCTION_ENTRY_SIZE)?;
+        self.table.get(start..start + FUNCTION_NAME_SIZE)
+    }
+
+    /// The raw instruction stream every function offset is relative to
+    pub fn code(&self) -> &'a [u8] {
+        self.code
+    }
+
+    pub fn function_body(&self, function: &FunctionEntry) -> &'a [u8] {
+        let start = function.offset as usize;
+        &self.code[start..start + function.length as usize]
+    }
+
+    /// Copies every function out in table order, ready to be rebuilt with `build_script`
+    pub fn to_functions(&self) -> Vec<ScriptFunction> {
+        self.functions
+            .iter()
+            .map(|function| ScriptFunction {
+                name: function.name.clone(),
+                body: self.function_body(function).to_vec(),
+            })
+            .collect()
+    }
+
+    /// Offset of the code section from the start of the script buffer
+    pub fn code_start(&self) -> usize {
+        HEADER_SIZE + self.functions.len() * FUNCTION_ENTRY_SIZE
+    }
+
+    /// Main scripts (including `cmn`) declare their character right at the start, effect scripts don't
+    pub fn is_main_script(&self) -> bool {
+        read_u32(self.code, CHARACTER_NAME_INSTRUCTION) == Some(CHARACTER_NAME_OPCODE)
+    }
+
+    /// Shortname of the character this script belongs to (e.g. `sol`), only present in main scripts
+    pub fn character_shortname(&self) -> Option<&'a [u8]> {
+        let field = self
+            .code
+            .get(CHARACTER_NAME_OFFSET..CHARACTER_NAME_OFFSET + FUNCTION_NAME_SIZE)?;
+        let len = field.iter().position(|&b| b == 0)?;
+
+        Some(&field[..len])
+    }
+}
+
+/// A function ready to be laid out into a new script
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFunction {
+    pub name: String,
+    pub body: Vec<u8>,
+}
+
+/// Builds a script buffer, functions are placed in the code section in the order given
+pub fn build_script(functions: &[ScriptFunction]) -> Result<Vec<u8>, ScriptError> {
+    let code_size = functions.iter().map(|f| f.body.len()).sum::<usize>();
+    let mut script =
+        Vec::with_capacity(HEADER_SIZE + functions.len() * FUNCTION_ENTRY_SIZE + code_size);
+
+    script.extend_from_slice(&(functions.len() as u32).to_le_bytes());
+
+    let mut offset = 0u32;
+    for function in functions {
+        script.extend_from_slice(&write_fixed_str(&function.name)?);
+        script.extend_from_slice(&offset.to_le_bytes());
+        offset += function.body.len() as u32;
+    }
+
+    for function in functions {
+        script.extend_from_slice(&function.body);
+    }
+
+    Ok(script)
+}
+
+/// Hex encoded SHA-256 of a script buffer, matches what `sha256sum` prints for the same file
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads a null padded fixed width string, anything after the first null is ignored
+pub(crate) fn read_fixed_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// Null pads a string into a name field, fails if it doesn't fit
+pub(crate) fn write_fixed_str(name: &str) -> Result<[u8; FUNCTION_NAME_SIZE], ScriptError> {
+    let bytes = name.as_bytes();
+    if bytes.len() > FUNCTION_NAME_SIZE {
+        return Err(ScriptError::NameTooLong(name.to_string()));
+    }
+
+    let mut field = [0u8; FUNCTION_NAME_SIZE];
+    field[..bytes.len()].copy_from_slice(bytes);
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Table entry pointing at `offset` in the code section
+    fn entry(name: &str, offset: u32) -> Vec<u8> {
+        let mut entry = write_fixed_str(name).unwrap().to_vec();
+        entry.extend_from_slice(&offset.to_le_bytes());
+        entry
+    }
+
+    fn script(entries: &[(&str, u32)], code: &[u8]) -> Vec<u8> {
+        let mut script = (entries.len() as u32).to_le_bytes().to_vec();
+        for &(name, offset) in entries {
+            script.extend(entry(name, offset));
+        }
+        script.extend_from_slice(code);
+        script
+    }
+
+    #[test]
+    fn parse() {
+        let data = script(&[("NmlAtk5A", 0), ("NmlAtk5B", 8)], &[0xAA; 12]);
+        let script = BBScript::parse(&data).unwrap();
+
+        assert_eq!(script.code_start(), 0x4 + 2 * 0x24);
+        assert_eq!(
+            script.functions(),
+            &[
+                FunctionEntry {
+                    name: "NmlAtk5A".into(),
+                    offset: 0,
+                    length: 8,
+                },
+                FunctionEntry {
+                    name: "NmlAtk5B".into(),
+                    offset: 8,
+                    length: 4,
+                },
+            ]
+        );
+        assert_eq!(script.function_body(&script.functions()[1]), &[0xAA; 4]);
+        assert!(script.function("NmlAtk5C").is_none());
+    }
+
+    #[test]
+    fn parse_rejects_bad_layouts() {
+        assert_eq!(
+            BBScript::parse(&[1, 0]).unwrap_err(),
+            ScriptError::MissingHeader(2)
+        );
+        assert_eq!(
+            BBScript::parse(&[0xFF; 4]).unwrap_err(),
+            ScriptError::TableOutOfBounds {
+                count: u32::MAX,
+                size: 4,
+            }
+        );
+        assert_eq!(
+            BBScript::parse(&script(&[("NmlAtk5A", 5)], &[0; 4])).unwrap_err(),
+            ScriptError::FunctionOutOfBounds {
+                name: "NmlAtk5A".into(),
+                offset: 5,
+                code_size: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn build_script_round_trip() {
+        let functions = vec![
+            ScriptFunction {
+                name: "CharaInit".into(),
+                body: vec![1, 2, 3, 4],
+            },
+            ScriptFunction {
+                name: "NmlAtk5A".into(),
+                body: vec![5, 6],
+            },
+        ];
+
+        let data = build_script(&functions).unwrap();
+        assert_eq!(
+            data,
+            script(&[("CharaInit", 0), ("NmlAtk5A", 4)], &[1, 2, 3, 4, 5, 6])
+        );
+        assert_eq!(BBScript::parse(&data).unwrap().to_functions(), functions);
+
+        let too_long = ScriptFunction {
+            name: "x".repeat(FUNCTION_NAME_SIZE + 1),
+            body: Vec::new(),
+        };
+        assert!(build_script(&[too_long]).is_err());
+    }
+
+    #[test]
+    fn character_shortname() {
+        // startState "CharaInit", then characterName "sol"
+        let mut code = 0u32.to_le_bytes().to_vec();
+        code.extend_from_slice(&write_fixed_str("CharaInit").unwrap());
+        code.extend_from_slice(&CHARACTER_NAME_OPCODE.to_le_bytes());
+        code.extend_from_slice(&write_fixed_str("sol").unwrap());
+
+        let data = script(&[("CharaInit", 0)], &code);
+        let main = BBScript::parse(&data).unwrap();
+        assert!(main.is_main_script());
+        assert_eq!(main.character_shortname(), Some(&b"sol"[..]));
+
+        let data = script(&[("CharaInit", 0)], &code[..0x24]);
+        assert!(!BBScript::parse(&data).unwrap().is_main_script());
+    }
+}
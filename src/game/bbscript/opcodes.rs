@@ -0,0 +1,390 @@
+//! Opcode table used to walk BBScript instruction streams.
+//!
+//! Every instruction is a u32 opcode followed by a fixed layout of arguments,
+//! so knowing the layout of an opcode is enough to find where the next one starts.
+//! The built in list only covers opcodes that have been figured out so far, an
+//! `opcodes.json` next to the DLL (same format as the built in `opcodes.json` in
+//! this folder) can add to or correct it without rebuilding.
+
+use super::asm::parse_int;
+use super::FUNCTION_NAME_SIZE;
+use crate::error::ModError;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Size of the opcode id at the start of every instruction
+pub const OPCODE_SIZE: usize = 0x4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArgType {
+    /// Little endian i32
+    Int,
+    /// Null padded string, same width as function names
+    String32,
+}
+
+impl ArgType {
+    pub fn size(&self) -> usize {
+        match self {
+            ArgType::Int => 0x4,
+            ArgType::String32 => FUNCTION_NAME_SIZE,
+        }
+    }
+}
+
+/// What an opcode does to the structure of a script, used for indenting and block matching,
+/// plus the few instructions frame data is worked out from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OpcodeKind {
+    Normal,
+    BeginState,
+    EndState,
+    BeginSubroutine,
+    EndSubroutine,
+    BeginIf,
+    Else,
+    EndIf,
+    BeginUpon,
+    EndUpon,
+    /// Turns the hitboxes of the current sprite on
+    HitActivate,
+    /// Marks the point the attack is over and the character is recovering
+    Recovery,
+}
+
+impl Default for OpcodeKind {
+    fn default() -> Self {
+        OpcodeKind::Normal
+    }
+}
+
+/// What an argument refers to, lets tools follow references without knowing every opcode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArgRole {
+    /// Name of the state or subroutine the instruction starts
+    FunctionName,
+    StateRef,
+    SubroutineRef,
+    /// State in the effect script that gets spawned as an object
+    ObjectRef,
+    Sprite,
+    /// Number of frames a sprite is shown for
+    Duration,
+    Sound,
+    LabelDef,
+    LabelRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArgInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: ArgType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<ArgRole>,
+    /// Name of the table in `enums` that gives values of this argument a name
+    #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+    pub enum_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeInfo {
+    pub id: u32,
+    pub name: String,
+    pub kind: OpcodeKind,
+    pub args: Vec<ArgInfo>,
+    /// Pseudo-code template for the decompiler, `{arg name}` gets replaced with the argument
+    pub format: Option<String>,
+}
+
+impl OpcodeInfo {
+    /// Full size of the instruction including the opcode itself
+    pub fn size(&self) -> usize {
+        OPCODE_SIZE + self.args.iter().map(|arg| arg.ty.size()).sum::<usize>()
+    }
+
+    /// Index of the first argument with the given role
+    pub fn arg_with_role(&self, role: ArgRole) -> Option<usize> {
+        self.args.iter().position(|arg| arg.role == Some(role))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OpcodeTable {
+    by_id: HashMap<u32, OpcodeInfo>,
+    by_name: HashMap<String, u32>,
+    enums: HashMap<String, HashMap<i32, String>>,
+}
+
+/// Opcodes compiled into the DLL, used when there's no external table or it fails to load
+const BUILTIN_OPCODES: &str = include_str!("opcodes.json");
+
+/// Layout of an opcode table file
+#[derive(Debug, Deserialize)]
+struct OpcodeFile {
+    opcodes: Vec<OpcodeEntry>,
+    /// Named values, keyed by enum name and then by the value written as a string
+    #[serde(default)]
+    enums: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpcodeEntry {
+    id: u32,
+    name: String,
+    #[serde(default)]
+    kind: OpcodeKind,
+    #[serde(default)]
+    args: Vec<ArgInfo>,
+    /// Full instruction size, anything the args don't cover is filled with unnamed ints
+    size: Option<usize>,
+    format: Option<String>,
+}
+
+impl OpcodeEntry {
+    fn into_info(self) -> Result<OpcodeInfo, ModError> {
+        let mut info = OpcodeInfo {
+            id: self.id,
+            name: self.name,
+            kind: self.kind,
+            args: self.args,
+            format: self.format,
+        };
+
+        if let Some(size) = self.size {
+            if size < info.size() || (size - info.size()) % ArgType::Int.size() != 0 {
+                return Err(ModError::InvalidOpcodeTable(format!(
+                    "size {:#X} of `{}` doesn't match its arguments",
+                    size, info.name
+                )));
+            }
+
+            while info.size() < size {
+                info.args.push(ArgInfo {
+                    name: format!("unknown{}", info.args.len()),
+                    ty: ArgType::Int,
+                    role: None,
+                    enum_name: None,
+                });
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+impl OpcodeTable {
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_OPCODES).expect("Built in opcode table is invalid")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ModError> {
+        let file: OpcodeFile =
+            serde_json::from_str(json).map_err(|e| ModError::InvalidOpcodeTable(e.to_string()))?;
+
+        let mut table = Self::default();
+        for entry in file.opcodes {
+            table.insert(entry.into_info()?);
+        }
+
+        for (enum_name, values) in file.enums {
+            let mut named = HashMap::with_capacity(values.len());
+            for (value, name) in values {
+                let value = parse_int(&value).ok_or_else(|| {
+                    ModError::InvalidOpcodeTable(format!(
+                        "`{}` in enum `{}` is not a number",
+                        value, enum_name
+                    ))
+                })?;
+                named.insert(value, name);
+            }
+
+            table.enums.insert(enum_name, named);
+        }
+
+        Ok(table)
+    }
+
+    /// Built in table with the entries from an opcode table file layered over it,
+    /// falls back to only the built in table if the file is missing or broken
+    pub fn load(path: &Path) -> Self {
+        let mut table = Self::builtin();
+
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(_) => {
+                debug!(
+                    "No opcode table at `{}`, using built in table",
+                    path.display()
+                );
+                return table;
+            }
+        };
+
+        match Self::from_json(&json) {
+            Ok(external) => {
+                info!(
+                    "Loaded {} opcodes from `{}`",
+                    external.len(),
+                    path.display()
+                );
+
+                table.layer(external);
+            }
+            Err(e) => error!("`{}`: {}, using built in table", path.display(), e),
+        }
+
+        table
+    }
+
+    /// Puts the opcodes and enum values of `external` over the ones in this table
+    fn layer(&mut self, external: OpcodeTable) {
+        let mut opcodes = external.by_id.values().cloned().collect::<Vec<_>>();
+        // keeps renames between the two tables the same every time
+        opcodes.sort_by_key(|info| info.id);
+
+        for info in opcodes {
+            self.insert(info);
+        }
+
+        for (enum_name, values) in external.enums {
+            self.enums.entry(enum_name).or_default().extend(values);
+        }
+    }
+
+    /// Adds an opcode, replacing any existing entry with the same id.
+    /// An opcode with another id that had the same name is renamed to `opcode{id}`.
+    pub fn insert(&mut self, info: OpcodeInfo) {
+        if let Some(old) = self.by_id.get(&info.id) {
+            if self.by_name.get(&old.name) == Some(&info.id) {
+                self.by_name.remove(&old.name);
+            }
+        }
+
+        match self.by_name.insert(info.name.clone(), info.id) {
+            Some(previous) if previous != info.id => {
+                if let Some(displaced) = self.by_id.get_mut(&previous) {
+                    warn!(
+                        "Opcode {} takes the name `{}` from opcode {}, calling that one `opcode{}`",
+                        info.id, info.name, previous, previous
+                    );
+                    displaced.name = format!("opcode{}", previous);
+                    self.by_name.insert(displaced.name.clone(), previous);
+                }
+            }
+            _ => {}
+        }
+
+        self.by_id.insert(info.id, info);
+    }
+
+    pub fn get(&self, id: u32) -> Option<&OpcodeInfo> {
+        self.by_id.get(&id)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&OpcodeInfo> {
+        self.by_name.get(name).and_then(|id| self.by_id.get(id))
+    }
+
+    /// Name for a value of an enum, e.g. the event an `upon` block runs on
+    pub fn enum_value(&self, enum_name: &str, value: i32) -> Option<&str> {
+        self.enums
+            .get(enum_name)
+            .and_then(|values| values.get(&value))
+            .map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(opcodes: &str, enums: &str) -> Result<OpcodeTable, ModError> {
+        OpcodeTable::from_json(&format!(
+            "{{\"opcodes\": [{}], \"enums\": {{{}}}}}",
+            opcodes, enums
+        ))
+    }
+
+    #[test]
+    fn pads_to_size() {
+        let table = table(
+            r#"{"id": 1, "name": "a", "args": [{"name": "x", "type": "Int"}], "size": 16}"#,
+            "",
+        )
+        .unwrap();
+
+        let opcode = table.get(1).unwrap();
+        assert_eq!(opcode.size(), 16);
+        assert_eq!(
+            opcode
+                .args
+                .iter()
+                .map(|arg| arg.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["x", "unknown1", "unknown2"]
+        );
+
+        // smaller than the arguments, or not a whole number of ints past them
+        for size in &[4, 10] {
+            let json = format!(
+                r#"{{"id": 1, "name": "a", "args": [{{"name": "x", "type": "Int"}}], "size": {}}}"#,
+                size
+            );
+            assert!(
+                self::table(&json, "").is_err(),
+                "size {} was accepted",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn parses_enum_values() {
+        let table = table(
+            "",
+            r#""Event": {"3": "Hit", "0x10": "Sixteen", "-1": "None"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(table.enum_value("Event", 3), Some("Hit"));
+        assert_eq!(table.enum_value("Event", 16), Some("Sixteen"));
+        assert_eq!(table.enum_value("Event", -1), Some("None"));
+        assert_eq!(table.enum_value("Event", 4), None);
+        assert_eq!(table.enum_value("Other", 3), None);
+
+        assert!(self::table("", r#""Event": {"three": "Hit"}"#).is_err());
+    }
+
+    #[test]
+    fn layers_external_tables() {
+        let mut layered = OpcodeTable::builtin();
+        layered.layer(
+            table(
+                r#"{"id": 26, "name": "dealDamage", "args": [{"name": "amount", "type": "Int"}]},
+                   {"id": 100, "name": "sprite"}"#,
+                r#""Tag": {"3": "Other"}"#,
+            )
+            .unwrap(),
+        );
+
+        // renamed in place
+        assert_eq!(layered.by_name("dealDamage").unwrap().id, 26);
+        assert!(layered.by_name("damage").is_none());
+
+        // a new opcode taking a built in name
+        assert_eq!(layered.by_name("sprite").unwrap().id, 100);
+        assert_eq!(layered.get(2).unwrap().name, "opcode2");
+        assert_eq!(layered.by_name("opcode2").unwrap().id, 2);
+
+        assert_eq!(layered.enum_value("Tag", 3), Some("Other"));
+        assert_eq!(layered.enum_value("Tag", 2), Some("Var"));
+    }
+}
@@ -0,0 +1,64 @@
+//! Checks that a main script and its `_ef` script agree on the objects that get spawned.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{ArgRole, OpcodeTable};
+use super::BBScript;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingObject {
+    /// `true` if the spawning function is in the effect script itself
+    pub from_effect: bool,
+    pub function: String,
+    pub object: String,
+}
+
+impl fmt::Display for MissingObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` in the {} script spawns `{}` which isn't in the effect script",
+            self.function,
+            if self.from_effect { "effect" } else { "main" },
+            self.object
+        )
+    }
+}
+
+/// Every object spawned by either script that the effect script doesn't have
+pub fn missing_objects(
+    main: &BBScript,
+    effect: &BBScript,
+    table: &OpcodeTable,
+) -> Vec<MissingObject> {
+    let mut missing = Vec::new();
+
+    for (from_effect, script) in [(false, main), (true, effect)] {
+        for function in script.functions() {
+            for item in instructions(script.function_body(function), table) {
+                let instruction = match item {
+                    Item::Instruction(instruction) => instruction,
+                    _ => break,
+                };
+
+                for (info, arg) in instruction.opcode.args.iter().zip(&instruction.args) {
+                    match (info.role, arg.as_str()) {
+                        (Some(ArgRole::ObjectRef), Some(object))
+                            if effect.function(object).is_none() =>
+                        {
+                            missing.push(MissingObject {
+                                from_effect,
+                                function: function.name.clone(),
+                                object: object.to_string(),
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    missing
+}
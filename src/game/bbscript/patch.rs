@@ -0,0 +1,287 @@
+//! `.bbpatch` files, function level edits applied over the vanilla script.
+//!
+//! A patch is a list of operations, each one starting with a directive line:
+//!
+//! ```text
+//! @remove "NmlAtk5A"
+//!
+//! @replace "NmlAtk5B"
+//! startState "NmlAtk5B"
+//!     sprite "sol201_00", 4
+//! endState
+//!
+//! @add "MyNewState"
+//! startState "MyNewState"
+//! endState
+//! ```
+//!
+//! Everything up to the next directive is assembler source for that function.
+
+use super::asm::{assemble_lines, parse_line};
+use super::disasm::Arg;
+use super::opcodes::OpcodeTable;
+use super::{BBScript, ScriptFunction};
+use crate::error::ScriptError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    /// Appends a new function to the end of the table
+    Add(ScriptFunction),
+    /// Swaps the body of an existing function, keeping its place in the table
+    Replace(ScriptFunction),
+    Remove(String),
+}
+
+impl PatchOp {
+    /// Name of the function this operation touches
+    pub fn target(&self) -> &str {
+        match self {
+            PatchOp::Add(function) | PatchOp::Replace(function) => &function.name,
+            PatchOp::Remove(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    pub operations: Vec<PatchOp>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Directive {
+    Add,
+    Replace,
+    Remove,
+}
+
+/// (directive, target name, line number, body lines) of an operation that's still being read
+type PendingOperation<'a> = (Directive, String, usize, Vec<(usize, &'a str)>);
+
+impl Patch {
+    pub fn parse(source: &str, table: &OpcodeTable) -> Result<Self, ScriptError> {
+        let lines = source
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+            .collect::<Vec<_>>();
+
+        let mut pending: Vec<PendingOperation> = Vec::new();
+
+        for &(line_number, line) in &lines {
+            let error = |message: String| ScriptError::Assemble {
+                line: line_number,
+                message,
+            };
+
+            if !line.trim_start().starts_with('@') {
+                match pending.last_mut() {
+                    Some((.., body)) => body.push((line_number, line)),
+                    None if parse_line(line).map_err(error)?.is_none() => {}
+                    None => {
+                        return Err(error("Instruction before the first patch directive".into()))
+                    }
+                }
+                continue;
+            }
+
+            let (directive, args) = parse_line(line).map_err(error)?.unwrap_or_default();
+            let directive = match directive {
+                "@add" => Directive::Add,
+                "@replace" => Directive::Replace,
+                "@remove" => Directive::Remove,
+                _ => return Err(error(format!("Unknown patch directive `{}`", directive))),
+            };
+
+            let name = match args.as_slice() {
+                [Arg::Str(name)] => name.clone(),
+                _ => return Err(error("Patch directives take a single function name".into())),
+            };
+
+            pending.push((directive, name, line_number, Vec::new()));
+        }
+
+        let mut operations = Vec::with_capacity(pending.len());
+        for (directive, name, line_number, body) in pending {
+            let error = |message: String| ScriptError::Assemble {
+                line: line_number,
+                message,
+            };
+
+            let mut functions = assemble_lines(body, table)?;
+
+            let function = match (directive, functions.len()) {
+                (Directive::Remove, 0) => {
+                    operations.push(PatchOp::Remove(name));
+                    continue;
+                }
+                (Directive::Remove, _) => {
+                    return Err(error(format!("`@remove \"{}\"` can't have a body", name)))
+                }
+                (_, 1) => functions.remove(0),
+                (_, count) => {
+                    return Err(error(format!(
+                        "`{}` must contain exactly one state or subroutine, found {}",
+                        name, count
+                    )))
+                }
+            };
+
+            if function.name != name {
+                return Err(error(format!(
+                    "Body of `{}` defines `{}` instead",
+                    name, function.name
+                )));
+            }
+
+            operations.push(match directive {
+                Directive::Add => PatchOp::Add(function),
+                _ => PatchOp::Replace(function),
+            });
+        }
+
+        Ok(Self { operations })
+    }
+
+    /// Patch that turns `base` into `modded`, only functions that changed are included
+    pub fn diff(base: &BBScript, modded: &BBScript) -> Self {
+        let mut operations = Vec::new();
+
+        for function in modded.functions() {
+            let body = modded.function_body(function);
+            let changed = ScriptFunction {
+                name: function.name.clone(),
+                body: body.to_vec(),
+            };
+
+            match base.function(&function.name) {
+                Some(original) if base.function_body(original) == body => {}
+                Some(_) => operations.push(PatchOp::Replace(changed)),
+                None => operations.push(PatchOp::Add(changed)),
+            }
+        }
+
+        for function in base.functions() {
+            if modded.function(&function.name).is_none() {
+                operations.push(PatchOp::Remove(function.name.clone()));
+            }
+        }
+
+        Self { operations }
+    }
+
+    /// Applies the patch over the functions of a script
+    pub fn apply_to(&self, functions: &mut Vec<ScriptFunction>) -> Result<(), ScriptError> {
+        for operation in &self.operations {
+            let position = functions.iter().position(|f| f.name == operation.target());
+
+            match (operation, position) {
+                (PatchOp::Add(function), None) => functions.push(function.clone()),
+                (PatchOp::Replace(function), Some(index)) => functions[index] = function.clone(),
+                (PatchOp::Remove(_), Some(index)) => {
+                    functions.remove(index);
+                }
+                (PatchOp::Add(function), Some(_)) => {
+                    return Err(ScriptError::Patch(format!(
+                        "can't add `{}`, it already exists",
+                        function.name
+                    )))
+                }
+                (_, None) => {
+                    return Err(ScriptError::Patch(format!(
+                        "no function named `{}`",
+                        operation.target()
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::{assemble, assemble_functions};
+
+    const VANILLA: &str =
+        "startState \"NmlAtk5A\"\ndamage 10\nendState\nstartState \"NmlAtk5B\"\nendState\n";
+
+    fn names(functions: &[ScriptFunction]) -> Vec<&str> {
+        functions.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_and_apply() {
+        let table = OpcodeTable::builtin();
+        let patch = Patch::parse(
+            "# balance changes\n@remove \"NmlAtk5B\"\n\n@replace \"NmlAtk5A\"\nstartState \"NmlAtk5A\"\ndamage 20\nendState\n@add \"NmlAtk5C\"\nstartState \"NmlAtk5C\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+
+        let mut functions = assemble_functions(VANILLA, &table).unwrap();
+        patch.apply_to(&mut functions).unwrap();
+
+        let expected = assemble_functions(
+            "startState \"NmlAtk5A\"\ndamage 20\nendState\nstartState \"NmlAtk5C\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+        assert_eq!(functions, expected);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let table = OpcodeTable::builtin();
+
+        assert!(Patch::parse("damage 10\n", &table).is_err());
+        assert!(Patch::parse("@rename \"NmlAtk5A\"\n", &table).is_err());
+        assert!(Patch::parse(
+            "@remove \"NmlAtk5A\"\nstartState \"NmlAtk5A\"\nendState\n",
+            &table
+        )
+        .is_err());
+        assert!(Patch::parse(
+            "@add \"NmlAtk5A\"\nstartState \"NmlAtk5B\"\nendState\n",
+            &table
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn apply_errors() {
+        let table = OpcodeTable::builtin();
+        let mut functions = assemble_functions(VANILLA, &table).unwrap();
+
+        let add = Patch::parse(
+            "@add \"NmlAtk5A\"\nstartState \"NmlAtk5A\"\nendState\n",
+            &table,
+        );
+        assert!(add.unwrap().apply_to(&mut functions).is_err());
+
+        let remove = Patch::parse("@remove \"NmlAtk5C\"\n", &table);
+        assert!(remove.unwrap().apply_to(&mut functions).is_err());
+        assert_eq!(names(&functions), vec!["NmlAtk5A", "NmlAtk5B"]);
+    }
+
+    #[test]
+    fn diff_applies_back() {
+        let table = OpcodeTable::builtin();
+        let base = assemble(VANILLA, &table).unwrap();
+        let modded = assemble(
+            "startState \"NmlAtk5A\"\ndamage 20\nendState\nstartState \"NmlAtk5C\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+        let (base, modded) = (
+            BBScript::parse(&base).unwrap(),
+            BBScript::parse(&modded).unwrap(),
+        );
+
+        let patch = Patch::diff(&base, &modded);
+        let mut functions = base.to_functions();
+        patch.apply_to(&mut functions).unwrap();
+        assert_eq!(functions, modded.to_functions());
+    }
+}
@@ -0,0 +1,369 @@
+//! Preprocessor for text sources (`.bbs` and `.bbpatch`) run before they get assembled.
+//!
+//! ```text
+//! #include "shared/helpers.bbs"
+//! #define DAMAGE 30
+//! #define EXTRA_HIT 1
+//!
+//! damage DAMAGE
+//! #if EXTRA_HIT
+//!     hitActivate # only with the extra hit
+//! #endif
+//! ```
+//!
+//! Directives are `#include`, `#define`, `#undef`, `#if`, `#ifdef`, `#ifndef`,
+//! `#else` and `#endif`, written with no space after the `#`. Any other line
+//! starting with `#`, including `# if ...`, is a comment and left for the assembler.
+//! Defined names are replaced anywhere outside of strings and comments, `#if` is
+//! true for names defined to anything but `0`. Every output line remembers the file
+//! and line it came from so errors can point at the original source.
+
+use crate::error::ScriptError;
+
+use std::collections::HashMap;
+
+/// Includes can't be nested deeper than this
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const DIRECTIVES: [&str; 8] = [
+    "include", "define", "undef", "if", "ifdef", "ifndef", "else", "endif",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessed {
+    /// Source with every directive handled, ready for the assembler
+    pub text: String,
+    /// Where each line of `text` came from
+    pub origins: Vec<Origin>,
+}
+
+impl Preprocessed {
+    /// Points an assembler error at the file and line it came from
+    pub fn map_error(&self, error: ScriptError) -> ScriptError {
+        match error {
+            ScriptError::Assemble { line, message } => match self.origins.get(line.wrapping_sub(1))
+            {
+                Some(origin) => ScriptError::Source {
+                    file: origin.file.clone(),
+                    line: origin.line,
+                    message,
+                },
+                None => ScriptError::Assemble { line, message },
+            },
+            other => other,
+        }
+    }
+}
+
+/// Preprocesses `source`, read from `file`. `include` gets the file doing the including and the
+/// path it asked for, and returns the name and contents of the file to include.
+pub fn preprocess(
+    source: &str,
+    file: &str,
+    mut include: impl FnMut(&str, &str) -> Result<(String, String), String>,
+) -> Result<Preprocessed, ScriptError> {
+    let mut preprocessor = Preprocessor {
+        defines: HashMap::new(),
+        output: Preprocessed::default(),
+        files: Vec::new(),
+    };
+
+    preprocessor.process(source, file, &mut include)?;
+    Ok(preprocessor.output)
+}
+
+/// Gets the including file and the requested path, returns the included file's name and contents
+type IncludeFn<'a> = dyn FnMut(&str, &str) -> Result<(String, String), String> + 'a;
+
+struct Condition {
+    /// Whether the lines in the current branch are kept
+    active: bool,
+    /// Whether the enclosing branch is kept, an `#else` can't turn lines on inside a dead branch
+    parent_active: bool,
+    in_else: bool,
+    line: usize,
+}
+
+struct Preprocessor {
+    defines: HashMap<String, String>,
+    output: Preprocessed,
+    /// Files currently being processed, innermost last
+    files: Vec<String>,
+}
+
+impl Preprocessor {
+    fn process(
+        &mut self,
+        source: &str,
+        file: &str,
+        include: &mut IncludeFn,
+    ) -> Result<(), ScriptError> {
+        self.files.push(file.to_string());
+
+        let mut conditions: Vec<Condition> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| ScriptError::Source {
+                file: file.to_string(),
+                line: line_number,
+                message,
+            };
+
+            let active = conditions.last().map_or(true, |condition| condition.active);
+
+            let (directive, rest) = match parse_directive(line) {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        let line = self.substitute(line);
+                        self.push_line(&line, file, line_number);
+                    }
+                    continue;
+                }
+            };
+
+            match directive {
+                "if" | "ifdef" | "ifndef" => {
+                    let name = single_word(rest)
+                        .ok_or_else(|| error(format!("`#{}` needs a name", directive)))?;
+                    let value = match directive {
+                        "if" => self.is_true(name),
+                        "ifdef" => self.defines.contains_key(name),
+                        _ => !self.defines.contains_key(name),
+                    };
+
+                    conditions.push(Condition {
+                        active: active && value,
+                        parent_active: active,
+                        in_else: false,
+                        line: line_number,
+                    });
+                }
+                "else" => match conditions.last_mut() {
+                    Some(condition) if !condition.in_else => {
+                        condition.in_else = true;
+                        condition.active = condition.parent_active && !condition.active;
+                    }
+                    Some(_) => return Err(error("Second `#else` for the same `#if`".into())),
+                    None => return Err(error("`#else` without `#if`".into())),
+                },
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(error("`#endif` without `#if`".into()));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let rest = rest.trim();
+                    let (name, value) = match rest.find(char::is_whitespace) {
+                        Some(split) => (&rest[..split], rest[split..].trim()),
+                        None => (rest, ""),
+                    };
+
+                    if !is_identifier(name) {
+                        return Err(error(format!("`{}` is not a valid name to define", name)));
+                    }
+
+                    let value = self.substitute(value);
+                    self.defines.insert(name.to_string(), value);
+                }
+                "undef" => {
+                    let name =
+                        single_word(rest).ok_or_else(|| error("`#undef` needs a name".into()))?;
+                    self.defines.remove(name);
+                }
+                "include" => {
+                    let path = rest
+                        .trim()
+                        .strip_prefix('"')
+                        .and_then(|path| path.strip_suffix('"'))
+                        .ok_or_else(|| error("`#include` needs a quoted path".into()))?;
+
+                    let (included_file, included_source) = include(file, path).map_err(error)?;
+
+                    if self.files.len() >= MAX_INCLUDE_DEPTH {
+                        return Err(error(format!(
+                            "Includes are nested more than {} deep",
+                            MAX_INCLUDE_DEPTH
+                        )));
+                    }
+
+                    if self.files.contains(&included_file) {
+                        return Err(error(format!(
+                            "`{}` ends up including itself ({} -> `{}`)",
+                            included_file,
+                            self.files
+                                .iter()
+                                .map(|f| format!("`{}`", f))
+                                .collect::<Vec<_>>()
+                                .join(" -> "),
+                            included_file
+                        )));
+                    }
+
+                    self.process(&included_source, &included_file, include)?;
+                }
+                _ => unreachable!("`parse_directive` only returns known directives"),
+            }
+        }
+
+        if let Some(condition) = conditions.last() {
+            return Err(ScriptError::Source {
+                file: file.to_string(),
+                line: condition.line,
+                message: "`#if` is never closed with `#endif`".into(),
+            });
+        }
+
+        self.files.pop();
+        Ok(())
+    }
+
+    fn push_line(&mut self, line: &str, file: &str, line_number: usize) {
+        self.output.text.push_str(line);
+        self.output.text.push('\n');
+        self.output.origins.push(Origin {
+            file: file.to_string(),
+            line: line_number,
+        });
+    }
+
+    fn is_true(&self, name: &str) -> bool {
+        match self.defines.get(name) {
+            Some(value) => value != "0",
+            None => name.parse::<i64>().map_or(false, |value| value != 0),
+        }
+    }
+
+    /// Replaces defined names outside of strings and comments
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.is_empty() {
+            return line.to_string();
+        }
+
+        let mut out = String::with_capacity(line.len());
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '"' => {
+                    out.push(c);
+                    let mut escaped = false;
+                    for (_, c) in chars.by_ref() {
+                        out.push(c);
+                        match c {
+                            '\\' if !escaped => escaped = true,
+                            '"' if !escaped => break,
+                            _ => escaped = false,
+                        }
+                    }
+                }
+                '#' => {
+                    out.push_str(&line[start..]);
+                    break;
+                }
+                c if c == '_' || c.is_ascii_alphabetic() => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(index, c)) = chars.peek() {
+                        if c == '_' || c.is_ascii_alphanumeric() {
+                            end = index + c.len_utf8();
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+
+                    let word = &line[start..end];
+                    out.push_str(self.defines.get(word).map_or(word, String::as_str));
+                }
+                c => out.push(c),
+            }
+        }
+
+        out
+    }
+}
+
+/// Splits `#name rest` into the directive name and the rest, `None` for lines that aren't a directive
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start().strip_prefix('#')?;
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+
+    if DIRECTIVES.contains(&name) {
+        Some((name, rest))
+    } else {
+        None
+    }
+}
+
+fn single_word(rest: &str) -> Option<&str> {
+    let mut words = rest.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(word), None) => Some(word),
+        _ => None,
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<Preprocessed, ScriptError> {
+        preprocess(source, "test.bbs", |_, path| {
+            Err(format!("No `{}` here", path))
+        })
+    }
+
+    #[test]
+    fn comments_are_not_directives() {
+        let source = "# if you read this\n#iff\n# endif\nendState\n";
+        assert_eq!(run(source).unwrap().text, source);
+    }
+
+    #[test]
+    fn conditions_and_defines() {
+        let source =
+            "#define DAMAGE 30\n#if DAMAGE\ndamage DAMAGE # DAMAGE\n#else\ndamage 0\n#endif\n";
+        let preprocessed = run(source).unwrap();
+
+        assert_eq!(preprocessed.text, "damage 30 # DAMAGE\n");
+        assert_eq!(preprocessed.origins[0].line, 3);
+    }
+
+    #[test]
+    fn unclosed_condition() {
+        assert!(run("#if X\n").is_err());
+        assert!(run("#endif\n").is_err());
+    }
+
+    #[test]
+    fn include_loops_and_depth() {
+        let looped = preprocess("#include \"a.bbs\"\n", "a.bbs", |_, path| {
+            Ok((path.to_string(), "#include \"a.bbs\"\n".to_string()))
+        });
+        assert!(looped.unwrap_err().to_string().contains("including itself"));
+
+        let mut count = 0;
+        let deep = preprocess("#include \"next\"\n", "0.bbs", |_, _| {
+            count += 1;
+            Ok((format!("{}.bbs", count), "#include \"next\"\n".to_string()))
+        });
+        assert!(deep.unwrap_err().to_string().contains("nested more than"));
+    }
+}
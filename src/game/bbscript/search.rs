@@ -0,0 +1,188 @@
+//! Finds instructions across any number of scripts, e.g. every state in the cast
+//! that uses an opcode, references a sprite or passes a certain value.
+
+use super::asm::parse_int;
+use super::disasm::{instructions, Item};
+use super::opcodes::OpcodeTable;
+use super::BBScript;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryKind {
+    /// Opcode name or id
+    Opcode,
+    /// Part of any string argument, ignoring case
+    String,
+    /// Exact value of any integer argument
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Opcode(String),
+    OpcodeId(u32),
+    String(String),
+    Value(i32),
+}
+
+impl Query {
+    pub fn new(kind: QueryKind, text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("Nothing to search for".into());
+        }
+
+        match kind {
+            QueryKind::Opcode => Ok(match parse_int(text) {
+                Some(id) => Query::OpcodeId(id as u32),
+                None => Query::Opcode(text.to_string()),
+            }),
+            QueryKind::String => Ok(Query::String(text.to_lowercase())),
+            QueryKind::Value => parse_int(text)
+                .map(Query::Value)
+                .ok_or_else(|| format!("`{}` is not a number", text)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub script: String,
+    pub function: String,
+    /// Offset of the instruction from the start of the function
+    pub offset: usize,
+    /// The instruction as the disassembler writes it
+    pub instruction: String,
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} +{:#X}: {}",
+            self.script, self.function, self.offset, self.instruction
+        )
+    }
+}
+
+/// Searches `(script name, script)` pairs, matches come back in script and code order
+pub fn search(scripts: &[(&str, &BBScript)], query: &Query, table: &OpcodeTable) -> Vec<Match> {
+    let mut matches = Vec::new();
+
+    for (script_name, script) in scripts {
+        let mut functions = script.functions().iter().collect::<Vec<_>>();
+        functions.sort_by_key(|f| f.offset);
+        functions.dedup_by_key(|f| f.offset);
+
+        for function in functions {
+            for item in instructions(script.function_body(function), table) {
+                let instruction = match item {
+                    Item::Instruction(instruction) => instruction,
+                    _ => break,
+                };
+
+                let found = match query {
+                    Query::Opcode(name) => instruction.opcode.name.eq_ignore_ascii_case(name),
+                    Query::OpcodeId(id) => instruction.opcode.id == *id,
+                    Query::String(text) => instruction
+                        .args
+                        .iter()
+                        .filter_map(|arg| arg.as_str())
+                        .any(|arg| arg.to_lowercase().contains(text.as_str())),
+                    Query::Value(value) => instruction
+                        .args
+                        .iter()
+                        .any(|arg| arg.as_int() == Some(*value)),
+                };
+
+                if found {
+                    matches.push(Match {
+                        script: script_name.to_string(),
+                        function: function.name.clone(),
+                        offset: instruction.offset,
+                        instruction: instruction.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    /// `(function, offset)` of every match for `kind` and `text` in two small scripts
+    fn found(kind: QueryKind, text: &str) -> Vec<(String, usize)> {
+        let table = OpcodeTable::builtin();
+        let sol = assemble(
+            "startState \"NmlAtk5A\"\nsprite \"sol201_00\", 4\ndamage 30\nendState\n",
+            &table,
+        )
+        .unwrap();
+        let ky = assemble(
+            "startState \"NmlAtk5B\"\nsprite \"kyk201_00\", 30\nendState\n",
+            &table,
+        )
+        .unwrap();
+        let (sol, ky) = (
+            BBScript::parse(&sol).unwrap(),
+            BBScript::parse(&ky).unwrap(),
+        );
+
+        search(
+            &[("sol", &sol), ("ky", &ky)],
+            &Query::new(kind, text).unwrap(),
+            &table,
+        )
+        .into_iter()
+        .map(|found| (format!("{}/{}", found.script, found.function), found.offset))
+        .collect()
+    }
+
+    fn at(function: &str, offset: usize) -> (String, usize) {
+        (function.to_string(), offset)
+    }
+
+    #[test]
+    fn opcode_queries() {
+        assert_eq!(
+            found(QueryKind::Opcode, "DAMAGE"),
+            vec![at("sol/NmlAtk5A", 0x4C)]
+        );
+        assert_eq!(
+            found(QueryKind::Opcode, "0x2"),
+            vec![at("sol/NmlAtk5A", 0x24), at("ky/NmlAtk5B", 0x24)]
+        );
+        assert_eq!(
+            Query::new(QueryKind::Opcode, " 26 "),
+            Ok(Query::OpcodeId(26))
+        );
+    }
+
+    #[test]
+    fn string_queries() {
+        assert_eq!(
+            found(QueryKind::String, "201_00"),
+            vec![at("sol/NmlAtk5A", 0x24), at("ky/NmlAtk5B", 0x24)]
+        );
+        assert_eq!(
+            found(QueryKind::String, "NMLATK5B"),
+            vec![at("ky/NmlAtk5B", 0x0)]
+        );
+    }
+
+    #[test]
+    fn value_queries() {
+        assert_eq!(
+            found(QueryKind::Value, "30"),
+            vec![at("sol/NmlAtk5A", 0x4C), at("ky/NmlAtk5B", 0x24)]
+        );
+        assert!(Query::new(QueryKind::Value, "thirty").is_err());
+        assert!(Query::new(QueryKind::String, "  ").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Pulls every embedded string out of a script along with where it is and what it names.
+//!
+//! Offsets are from the start of the script buffer and point at the start of the
+//! 0x20 byte field, so a renaming tool can write a new name straight over it.
+//! A function that can't be decoded to the end gets an `incomplete` row at the
+//! point decoding stopped, strings past it are missing.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{ArgRole, OpcodeTable, OPCODE_SIZE};
+use super::{BBScript, FUNCTION_ENTRY_SIZE, HEADER_SIZE};
+
+use serde::Serialize;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum StringCategory {
+    /// Name in the function table
+    TableEntry,
+    /// Name a state or subroutine gives itself when it starts
+    FunctionName,
+    StateRef,
+    SubroutineRef,
+    /// Effect script state spawned as an object
+    Effect,
+    Sprite,
+    Sound,
+    /// String argument the opcode table doesn't give a role
+    Other,
+    /// Not a string, marks where decoding the function stopped
+    Incomplete,
+}
+
+impl StringCategory {
+    fn from_role(role: Option<ArgRole>) -> Self {
+        match role {
+            Some(ArgRole::FunctionName) => StringCategory::FunctionName,
+            Some(ArgRole::StateRef) => StringCategory::StateRef,
+            Some(ArgRole::SubroutineRef) => StringCategory::SubroutineRef,
+            Some(ArgRole::ObjectRef) => StringCategory::Effect,
+            Some(ArgRole::Sprite) => StringCategory::Sprite,
+            Some(ArgRole::Sound) => StringCategory::Sound,
+            _ => StringCategory::Other,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StringCategory::TableEntry => "table",
+            StringCategory::FunctionName => "function",
+            StringCategory::StateRef => "state",
+            StringCategory::SubroutineRef => "subroutine",
+            StringCategory::Effect => "effect",
+            StringCategory::Sprite => "sprite",
+            StringCategory::Sound => "sound",
+            StringCategory::Other => "other",
+            StringCategory::Incomplete => "incomplete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScriptString {
+    pub category: StringCategory,
+    /// Function the string is in, for table entries the function it names
+    pub function: String,
+    /// Offset of the string field from the start of the script buffer
+    pub offset: usize,
+    pub text: String,
+}
+
+/// Every string in the function table followed by every string argument in code order
+pub fn extract(script: &BBScript, table: &OpcodeTable) -> Vec<ScriptString> {
+    let mut strings = script
+        .functions()
+        .iter()
+        .enumerate()
+        .map(|(index, function)| ScriptString {
+            category: StringCategory::TableEntry,
+            function: function.name.clone(),
+            offset: HEADER_SIZE + index * FUNCTION_ENTRY_SIZE,
+            text: function.name.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut functions = script.functions().iter().collect::<Vec<_>>();
+    functions.sort_by_key(|f| f.offset);
+    functions.dedup_by_key(|f| f.offset);
+
+    for function in functions {
+        let function_start = script.code_start() + function.offset as usize;
+        let body = script.function_body(function);
+
+        for item in instructions(body, table) {
+            let instruction = match item {
+                Item::Instruction(instruction) => instruction,
+                other => {
+                    strings.push(ScriptString {
+                        category: StringCategory::Incomplete,
+                        function: function.name.clone(),
+                        offset: function_start + other.offset(),
+                        text: format!(
+                            "{:#X} bytes the opcode table can't decode",
+                            body.len() - other.offset()
+                        ),
+                    });
+                    break;
+                }
+            };
+
+            let mut field_offset = function_start + instruction.offset + OPCODE_SIZE;
+            for (info, arg) in instruction.opcode.args.iter().zip(&instruction.args) {
+                if let Some(text) = arg.as_str() {
+                    strings.push(ScriptString {
+                        category: StringCategory::from_role(info.role),
+                        function: function.name.clone(),
+                        offset: field_offset,
+                        text: text.to_string(),
+                    });
+                }
+
+                field_offset += info.ty.size();
+            }
+        }
+    }
+
+    strings
+}
+
+/// Writes the strings as CSV with a header row
+pub fn to_csv(strings: &[ScriptString]) -> String {
+    let mut csv = String::from("category,function,offset,text\n");
+
+    for string in strings {
+        let _ = writeln!(
+            csv,
+            "{},{},{:#X},{}",
+            string.category.name(),
+            csv_field(&string.function),
+            string.offset,
+            csv_field(&string.text)
+        );
+    }
+
+    csv
+}
+
+pub fn to_json(strings: &[ScriptString]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(strings)
+}
+
+/// Quotes a CSV field if it needs it
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    #[test]
+    fn offsets_and_categories() {
+        let table = OpcodeTable::builtin();
+        let data = assemble(
+            "startState \"A\"\nsprite \"a201_00\", 4\ncallSubroutine \"Sub\"\nenterState \"B\"\nendState\nbeginSubroutine \"Sub\"\ncreateObject \"Obj\", 0\nplaySound \"a_snd\"\ncharacterName \"a\"\nendSubroutine\n",
+            &table,
+        )
+        .unwrap();
+        let strings = extract(&BBScript::parse(&data).unwrap(), &table);
+
+        let found = strings
+            .iter()
+            .map(|string| {
+                (
+                    string.category,
+                    string.function.as_str(),
+                    string.text.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (StringCategory::TableEntry, "A", "A"),
+                (StringCategory::TableEntry, "Sub", "Sub"),
+                (StringCategory::FunctionName, "A", "A"),
+                (StringCategory::Sprite, "A", "a201_00"),
+                (StringCategory::SubroutineRef, "A", "Sub"),
+                (StringCategory::StateRef, "A", "B"),
+                (StringCategory::FunctionName, "Sub", "Sub"),
+                (StringCategory::Effect, "Sub", "Obj"),
+                (StringCategory::Sound, "Sub", "a_snd"),
+                (StringCategory::Other, "Sub", "a"),
+            ]
+        );
+
+        // every offset points at a null padded field holding the text
+        for string in &strings {
+            let field = &data[string.offset..string.offset + 0x20];
+            assert_eq!(&field[..string.text.len()], string.text.as_bytes());
+            assert_eq!(field[string.text.len()], 0);
+        }
+        assert_eq!(strings[3].offset, 0x74);
+    }
+
+    #[test]
+    fn marks_undecodable_functions() {
+        let table = OpcodeTable::builtin();
+        let data = assemble(
+            "startState \"A\"\n.bytes h\"ffffffff\"\nplaySound \"lost\"\nendState\n",
+            &table,
+        )
+        .unwrap();
+        let strings = extract(&BBScript::parse(&data).unwrap(), &table);
+
+        let incomplete = strings.last().unwrap();
+        assert_eq!(strings.len(), 3);
+        assert_eq!(incomplete.category, StringCategory::Incomplete);
+        assert_eq!(incomplete.offset, 0x28 + 0x24);
+        assert_eq!(incomplete.text, "0x2C bytes the opcode table can't decode");
+        assert!(to_csv(&strings)
+            .ends_with("incomplete,A,0x4C,0x2C bytes the opcode table can't decode\n"));
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("a\r\nb"), "\"a\r\nb\"");
+    }
+}
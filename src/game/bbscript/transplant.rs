@@ -0,0 +1,385 @@
+//! Copies a state from one character into another along with everything it needs to run.
+//!
+//! The state is followed through the subroutines it calls and the objects it spawns,
+//! and objects are followed through the effect script the same way. State jumps are
+//! left alone since they usually go to common states the target already has.
+//!
+//! Dependencies the target already has with the exact same body are shared, ones that
+//! clash with a different function in the target get a suffix and every reference to
+//! them in the copied code is rewritten. The result is a pair of full scripts that can
+//! be dropped into the Mods folder as the target's `.bbscript` files.
+
+use super::asm::encode_instruction;
+use super::disasm::{instructions, Arg, Item};
+use super::opcodes::{ArgRole, OpcodeTable};
+use super::{build_script, write_fixed_str, BBScript, ScriptFunction};
+use crate::error::ScriptError;
+
+use std::collections::HashMap;
+
+/// A character's main script and the `_ef` script that goes with it
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptPair<'s, 'a> {
+    pub main: &'s BBScript<'a>,
+    pub effect: Option<&'s BBScript<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Main,
+    Effect,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transplanted {
+    pub main: Vec<u8>,
+    /// `None` if nothing had to be copied into the effect script
+    pub effect: Option<Vec<u8>>,
+    /// Names of the copied functions as they are in the target
+    pub copied: Vec<String>,
+    /// `(donor name, target name)` of every function that had to be renamed
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Copies `state` from `donor` into `target`, replacing the target's function with the same
+/// name if it has one. Clashing dependencies get `suffix` appended to their name.
+pub fn transplant(
+    state: &str,
+    donor: ScriptPair,
+    target: ScriptPair,
+    suffix: &str,
+    table: &OpcodeTable,
+) -> Result<Transplanted, ScriptError> {
+    if donor.main.function(state).is_none() {
+        return Err(ScriptError::Transplant(format!(
+            "donor has no state named `{}`",
+            state
+        )));
+    }
+
+    let (main_needed, effect_needed) = dependencies(state, donor, table)?;
+
+    if !effect_needed.is_empty() && target.effect.is_none() {
+        return Err(ScriptError::Transplant(format!(
+            "`{}` spawns objects but there's no target effect script to copy them into",
+            state
+        )));
+    }
+
+    let mut copied = Vec::new();
+    let mut renamed = Vec::new();
+    let mut main_names = HashMap::new();
+    let mut effect_names = HashMap::new();
+
+    for (side, needed, names) in [
+        (Side::Main, &main_needed, &mut main_names),
+        (Side::Effect, &effect_needed, &mut effect_names),
+    ] {
+        let (from, to) = match side {
+            Side::Main => (Some(donor.main), Some(target.main)),
+            Side::Effect => (donor.effect, target.effect),
+        };
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue,
+        };
+
+        for name in needed {
+            let body = from.function(name).map(|f| from.function_body(f));
+            let existing = to.function(name).map(|f| to.function_body(f));
+
+            let new_name = match existing {
+                // the state itself always replaces whatever the target had
+                _ if side == Side::Main && name == state => name.clone(),
+                None => name.clone(),
+                Some(existing) if Some(existing) == body => {
+                    names.insert(name.clone(), None);
+                    continue;
+                }
+                Some(_) => {
+                    let new_name = format!("{}{}", name, suffix);
+                    write_fixed_str(&new_name)?;
+                    if to.function(&new_name).is_some() {
+                        return Err(ScriptError::Transplant(format!(
+                            "can't rename `{}`, the target already has a `{}`",
+                            name, new_name
+                        )));
+                    }
+
+                    renamed.push((name.clone(), new_name.clone()));
+                    new_name
+                }
+            };
+
+            copied.push(new_name.clone());
+            names.insert(name.clone(), Some(new_name));
+        }
+    }
+
+    let rename = |side: Side, role: ArgRole, name: &str| -> Option<String> {
+        let names = match (side, role) {
+            (Side::Main, ArgRole::ObjectRef) | (Side::Effect, _) => &effect_names,
+            (Side::Main, _) => &main_names,
+        };
+        names.get(name).cloned().flatten()
+    };
+
+    let main = splice(donor.main, target.main, &main_names, |body| {
+        rewrite(body, table, |role, name| rename(Side::Main, role, name))
+    })?;
+
+    let effect = match (donor.effect, target.effect) {
+        (Some(from), Some(to)) if effect_names.values().any(Option::is_some) => {
+            Some(splice(from, to, &effect_names, |body| {
+                rewrite(body, table, |role, name| rename(Side::Effect, role, name))
+            })?)
+        }
+        _ => None,
+    };
+
+    Ok(Transplanted {
+        main,
+        effect,
+        copied,
+        renamed,
+    })
+}
+
+/// Functions `state` needs from the donor's main and effect scripts, in the order they're found
+fn dependencies(
+    state: &str,
+    donor: ScriptPair,
+    table: &OpcodeTable,
+) -> Result<(Vec<String>, Vec<String>), ScriptError> {
+    let mut main_needed = Vec::new();
+    let mut effect_needed = Vec::new();
+    let mut queue = vec![(Side::Main, state.to_string())];
+
+    while let Some((side, name)) = queue.pop() {
+        let (script, needed) = match (side, donor.effect) {
+            (Side::Main, _) => (donor.main, &mut main_needed),
+            (Side::Effect, Some(effect)) => (effect, &mut effect_needed),
+            (Side::Effect, None) => {
+                return Err(ScriptError::Transplant(format!(
+                    "`{}` spawns objects but there's no donor effect script to copy them from",
+                    state
+                )))
+            }
+        };
+
+        // anything the donor doesn't have itself is most likely in the common scripts
+        let function = match script.function(&name) {
+            Some(function) if !needed.contains(&name) => function,
+            _ => continue,
+        };
+        needed.push(name);
+
+        for item in instructions(script.function_body(function), table) {
+            let instruction = match item {
+                Item::Instruction(instruction) => instruction,
+                _ => break,
+            };
+
+            for (info, arg) in instruction.opcode.args.iter().zip(&instruction.args) {
+                let reference = match arg.as_str() {
+                    Some(reference) => reference.to_string(),
+                    None => continue,
+                };
+
+                match (side, info.role) {
+                    (Side::Main, Some(ArgRole::SubroutineRef)) => {
+                        queue.push((Side::Main, reference))
+                    }
+                    (_, Some(ArgRole::ObjectRef))
+                    | (Side::Effect, Some(ArgRole::SubroutineRef))
+                    | (Side::Effect, Some(ArgRole::StateRef)) => {
+                        queue.push((Side::Effect, reference))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok((main_needed, effect_needed))
+}
+
+/// Target functions with the copied ones replacing same named functions or appended at the end
+fn splice(
+    from: &BBScript,
+    to: &BBScript,
+    names: &HashMap<String, Option<String>>,
+    rewrite: impl Fn(&[u8]) -> Result<Vec<u8>, ScriptError>,
+) -> Result<Vec<u8>, ScriptError> {
+    let mut functions = to.to_functions();
+
+    for function in from.functions() {
+        let new_name = match names.get(&function.name) {
+            Some(Some(new_name)) => new_name,
+            _ => continue,
+        };
+
+        let copy = ScriptFunction {
+            name: new_name.clone(),
+            body: rewrite(from.function_body(function))?,
+        };
+
+        match functions.iter_mut().find(|f| f.name == *new_name) {
+            Some(existing) => *existing = copy,
+            None => functions.push(copy),
+        }
+    }
+
+    build_script(&functions)
+}
+
+/// Re-encodes every instruction that references a renamed function, everything else is copied as is
+fn rewrite(
+    body: &[u8],
+    table: &OpcodeTable,
+    rename: impl Fn(ArgRole, &str) -> Option<String>,
+) -> Result<Vec<u8>, ScriptError> {
+    let mut out = Vec::with_capacity(body.len());
+
+    for item in instructions(body, table) {
+        let instruction = match item {
+            Item::Instruction(instruction) => instruction,
+            Item::Unknown { bytes, .. } | Item::Truncated { bytes, .. } => {
+                out.extend_from_slice(bytes);
+                break;
+            }
+        };
+
+        let start = instruction.offset;
+        let mut changed = false;
+        let args = instruction
+            .opcode
+            .args
+            .iter()
+            .zip(&instruction.args)
+            .map(|(info, arg)| match (info.role, arg) {
+                (Some(role), Arg::Str(name)) => match rename(role, name) {
+                    Some(new_name) if new_name != *name => {
+                        changed = true;
+                        Arg::Str(new_name)
+                    }
+                    _ => arg.clone(),
+                },
+                _ => arg.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        if changed {
+            out.extend(
+                encode_instruction(instruction.opcode, &args).map_err(ScriptError::Transplant)?,
+            );
+        } else {
+            out.extend_from_slice(&body[start..start + instruction.opcode.size()]);
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    const DONOR: &str = "startState \"Atk\"\ncallSubroutine \"Shared\"\ncallSubroutine \"Clash\"\ncreateObject \"Obj\", 0\nendState\nbeginSubroutine \"Shared\"\ndamage 1\nendSubroutine\nbeginSubroutine \"Clash\"\ndamage 2\nendSubroutine\n";
+    const DONOR_EF: &str = "startState \"Obj\"\nendState\n";
+    const TARGET: &str = "startState \"Atk\"\nendState\nbeginSubroutine \"Shared\"\ndamage 1\nendSubroutine\nbeginSubroutine \"Clash\"\ndamage 3\nendSubroutine\n";
+    const TARGET_EF: &str = "startState \"Other\"\nendState\n";
+
+    /// Transplants `Atk` between the given sources, effect scripts are left out when `None`
+    fn run(
+        donor_effect: Option<&str>,
+        target_effect: Option<&str>,
+    ) -> Result<Transplanted, ScriptError> {
+        let table = OpcodeTable::builtin();
+        let assembled = |source: &str| assemble(source, &table).unwrap();
+        let (donor, target) = (assembled(DONOR), assembled(TARGET));
+        let (donor_effect, target_effect) =
+            (donor_effect.map(assembled), target_effect.map(assembled));
+
+        let donor = BBScript::parse(&donor).unwrap();
+        let target = BBScript::parse(&target).unwrap();
+        let donor_effect = donor_effect
+            .as_deref()
+            .map(|data| BBScript::parse(data).unwrap());
+        let target_effect = target_effect
+            .as_deref()
+            .map(|data| BBScript::parse(data).unwrap());
+
+        transplant(
+            "Atk",
+            ScriptPair {
+                main: &donor,
+                effect: donor_effect.as_ref(),
+            },
+            ScriptPair {
+                main: &target,
+                effect: target_effect.as_ref(),
+            },
+            "_ky",
+            &table,
+        )
+    }
+
+    /// Every string argument in `name`'s body
+    fn references(script: &BBScript, name: &str) -> Vec<String> {
+        let table = OpcodeTable::builtin();
+        instructions(script.function_body(script.function(name).unwrap()), &table)
+            .filter_map(|item| match item {
+                Item::Instruction(instruction) => Some(instruction),
+                _ => None,
+            })
+            .skip(1)
+            .flat_map(|instruction| instruction.args)
+            .filter_map(|arg| arg.as_str().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn renames_clashes_and_shares_identical_functions() {
+        let transplanted = run(Some(DONOR_EF), Some(TARGET_EF)).unwrap();
+
+        assert_eq!(transplanted.copied, vec!["Atk", "Clash_ky", "Obj"]);
+        assert_eq!(
+            transplanted.renamed,
+            vec![("Clash".to_string(), "Clash_ky".to_string())]
+        );
+
+        let main = BBScript::parse(&transplanted.main).unwrap();
+        let names = main
+            .functions()
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Atk", "Shared", "Clash", "Clash_ky"]);
+        assert_eq!(references(&main, "Atk"), vec!["Shared", "Clash_ky", "Obj"]);
+    }
+
+    #[test]
+    fn copies_effect_objects() {
+        let transplanted = run(Some(DONOR_EF), Some(TARGET_EF)).unwrap();
+
+        let effect = transplanted.effect.unwrap();
+        let effect = BBScript::parse(&effect).unwrap();
+        let names = effect
+            .functions()
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Other", "Obj"]);
+    }
+
+    #[test]
+    fn needs_both_effect_scripts_for_objects() {
+        let error = run(Some(DONOR_EF), None).unwrap_err().to_string();
+        assert!(error.contains("no target effect script"));
+
+        let error = run(None, Some(TARGET_EF)).unwrap_err().to_string();
+        assert!(error.contains("no donor effect script"));
+    }
+}
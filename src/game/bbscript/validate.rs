@@ -0,0 +1,68 @@
+//! Sanity checks run on mod scripts before the game ever sees them.
+//!
+//! Anything that would make the game read out of bounds is an error, things
+//! that merely look odd are returned as warnings.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{OpcodeKind, OpcodeTable};
+use super::BBScript;
+use crate::error::ScriptError;
+
+/// Returns the warnings for a script that passed validation
+pub fn validate(data: &[u8], table: &OpcodeTable) -> Result<Vec<String>, ScriptError> {
+    let script = BBScript::parse(data)?;
+    let mut warnings = Vec::new();
+
+    if script.functions().is_empty() {
+        return Err(ScriptError::NoFunctions);
+    }
+
+    let first_offset = script
+        .functions()
+        .iter()
+        .map(|f| f.offset)
+        .min()
+        .unwrap_or(0);
+    if first_offset != 0 {
+        warnings.push(format!(
+            "{:#X} bytes of code before the first function",
+            first_offset
+        ));
+    }
+
+    for function in script.functions() {
+        let mut last_kind = None;
+
+        for item in instructions(script.function_body(function), table) {
+            match item {
+                Item::Instruction(instruction) => last_kind = Some(instruction.opcode.kind),
+                Item::Truncated { offset, opcode, .. } => {
+                    return Err(ScriptError::TruncatedInstruction {
+                        function: function.name.clone(),
+                        offset,
+                        opcode: opcode.name.clone(),
+                    })
+                }
+                Item::Unknown { offset, bytes } => {
+                    warnings.push(format!(
+                        "`{}`: unknown opcode {} at {:#X}, can't check the rest of the function",
+                        function.name,
+                        super::read_u32(bytes, 0).unwrap_or_default(),
+                        offset
+                    ));
+                    last_kind = None;
+                }
+            }
+        }
+
+        match last_kind {
+            Some(OpcodeKind::EndState) | Some(OpcodeKind::EndSubroutine) | None => {}
+            Some(_) => warnings.push(format!(
+                "`{}` doesn't end with endState or endSubroutine",
+                function.name
+            )),
+        }
+    }
+
+    Ok(warnings)
+}
@@ -0,0 +1,259 @@
+//! Cross references between functions, across the main, effect and common scripts.
+//!
+//! A graph covers one character: `{char}`, `{char}_ef`, `cmn` and `cmn_ef`.
+//! Objects are looked up in the effect script belonging to the referencing
+//! script first, subroutines and states in the referencing script and then in
+//! `cmn`. Other characters' scripts are never searched, they often reuse the
+//! same object and subroutine names.
+
+use super::disasm::{instructions, Item};
+use super::opcodes::{ArgRole, OpcodeTable};
+use super::BBScript;
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum EdgeKind {
+    /// `callSubroutine` and friends
+    Call,
+    /// Switches to another state
+    Jump,
+    /// Creates an object running an effect script state
+    Spawn,
+}
+
+impl EdgeKind {
+    fn from_role(role: ArgRole) -> Option<Self> {
+        match role {
+            ArgRole::SubroutineRef => Some(EdgeKind::Call),
+            ArgRole::StateRef => Some(EdgeKind::Jump),
+            ArgRole::ObjectRef => Some(EdgeKind::Spawn),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            EdgeKind::Call => "call",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Spawn => "spawn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub script: String,
+    pub function: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    /// Index into `CrossReferences::nodes`
+    pub from: usize,
+    /// `None` if no script has a function with the referenced name
+    pub to: Option<usize>,
+    /// Referenced name as written in the instruction
+    pub target: String,
+    pub kind: EdgeKind,
+    /// Offset of the referencing instruction in the code section of the `from` script
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CrossReferences {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl CrossReferences {
+    /// Builds the graph for one character's `(script name, script)` pairs,
+    /// e.g. `[("sol", ..), ("sol_ef", ..), ("cmn", ..), ("cmn_ef", ..)]`
+    pub fn build(scripts: &[(&str, &BBScript)], table: &OpcodeTable) -> Self {
+        let mut xrefs = Self::default();
+        // (script index, function name) -> node index
+        let mut lookup = HashMap::new();
+
+        for (script_index, (script_name, script)) in scripts.iter().enumerate() {
+            for function in script.functions() {
+                lookup
+                    .entry((script_index, function.name.clone()))
+                    .or_insert_with(|| {
+                        xrefs.nodes.push(Node {
+                            script: script_name.to_string(),
+                            function: function.name.clone(),
+                        });
+                        xrefs.nodes.len() - 1
+                    });
+            }
+        }
+
+        let index_of = |name: &str| {
+            scripts
+                .iter()
+                .position(|(script_name, _)| *script_name == name)
+        };
+
+        for (script_index, (script_name, script)) in scripts.iter().enumerate() {
+            let owner = script_name.strip_suffix("_ef").unwrap_or(script_name);
+            let search_order = |kind: EdgeKind| {
+                let names = match kind {
+                    EdgeKind::Spawn => vec![
+                        format!("{}_ef", owner),
+                        owner.to_string(),
+                        "cmn_ef".to_string(),
+                        "cmn".to_string(),
+                    ],
+                    EdgeKind::Call | EdgeKind::Jump => {
+                        vec![script_name.to_string(), "cmn".to_string()]
+                    }
+                };
+                let mut order = Vec::new();
+                for index in names.iter().filter_map(|name| index_of(name)) {
+                    if !order.contains(&index) {
+                        order.push(index);
+                    }
+                }
+                order
+            };
+
+            for function in script.functions() {
+                let from = lookup[&(script_index, function.name.clone())];
+
+                for item in instructions(script.function_body(function), table) {
+                    let instruction = match item {
+                        Item::Instruction(instruction) => instruction,
+                        _ => continue,
+                    };
+
+                    for (info, arg) in instruction.opcode.args.iter().zip(&instruction.args) {
+                        let kind = match info.role.and_then(EdgeKind::from_role) {
+                            Some(kind) => kind,
+                            None => continue,
+                        };
+                        let target = match arg.as_str() {
+                            Some(target) => target,
+                            None => continue,
+                        };
+
+                        let to = search_order(kind)
+                            .into_iter()
+                            .find_map(|index| lookup.get(&(index, target.to_string())).copied());
+
+                        xrefs.edges.push(Edge {
+                            from,
+                            to,
+                            target: target.to_string(),
+                            kind,
+                            offset: function.offset as usize + instruction.offset,
+                        });
+                    }
+                }
+            }
+        }
+
+        xrefs
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Graphviz output, unresolved targets are drawn as dashed red nodes
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph xrefs {\n    rankdir=LR;\n");
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let _ = writeln!(
+                dot,
+                "    n{} [label=\"{}\"];",
+                index,
+                dot_escape(&format!("{}/{}", node.script, node.function))
+            );
+        }
+
+        for edge in &self.edges {
+            let to = match edge.to {
+                Some(to) => format!("n{}", to),
+                None => {
+                    let missing = format!("\"missing/{}\"", dot_escape(&edge.target));
+                    let _ = writeln!(dot, "    {} [style=dashed, color=red];", missing);
+                    missing
+                }
+            };
+
+            let _ = writeln!(
+                dot,
+                "    n{} -> {} [label=\"{}\"];",
+                edge.from,
+                to,
+                edge.kind.label()
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bbscript::asm::assemble;
+
+    #[test]
+    fn resolves_within_the_character() {
+        let table = OpcodeTable::builtin();
+        let sources = [
+            ("sol", "startState \"NmlAtk5A\"\ncreateObject \"Shot\", 0\ncallSubroutine \"CmnSub\"\nendState\n"),
+            ("sol_ef", "startState \"Shot\"\nendState\n"),
+            ("ans", "startState \"NmlAtk5A\"\ncreateObject \"Shot\", 0\nendState\n"),
+            ("ans_ef", "startState \"Shot\"\nendState\n"),
+            ("cmn", "beginSubroutine \"CmnSub\"\nendSubroutine\n"),
+        ];
+        let data = sources
+            .iter()
+            .map(|(name, source)| (*name, assemble(source, &table).unwrap()))
+            .collect::<Vec<_>>();
+        let parsed = data
+            .iter()
+            .map(|(name, data)| (*name, BBScript::parse(data).unwrap()))
+            .collect::<Vec<_>>();
+        let script = |name: &str| &parsed.iter().find(|(n, _)| *n == name).unwrap().1;
+
+        // both characters are in the same set here, `ans_ef` comes first and would win by order
+        let xrefs = CrossReferences::build(
+            &[
+                ("ans_ef", script("ans_ef")),
+                ("sol", script("sol")),
+                ("sol_ef", script("sol_ef")),
+                ("cmn", script("cmn")),
+            ],
+            &table,
+        );
+        let targets = xrefs
+            .edges
+            .iter()
+            .map(|edge| {
+                let to = &xrefs.nodes[edge.to.unwrap()];
+                (edge.kind, format!("{}/{}", to.script, to.function))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                (EdgeKind::Spawn, "sol_ef/Shot".to_string()),
+                (EdgeKind::Call, "cmn/CmnSub".to_string()),
+            ]
+        );
+
+        let xrefs = CrossReferences::build(&[("ans", script("ans"))], &table);
+        assert_eq!(xrefs.edges[0].to, None);
+    }
+}
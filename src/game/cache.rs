@@ -0,0 +1,147 @@
+//! Keeps the contents of mod files in memory between loads.
+//!
+//! Every file the loader reads goes through here. A file only gets read from
+//! disk again when its modification time or size changed, so loading a match
+//! costs a metadata lookup per file instead of a full read.
+//!
+//! The cache also remembers which version of each file went into the scripts
+//! the game was last handed, since a file can change (and be reread) after it
+//! was injected.
+
+use super::bbscript;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub modified: SystemTime,
+    pub size: u64,
+    /// Hex SHA-256 of `data`, same format as `.base` pins
+    pub hash: String,
+    pub data: Vec<u8>,
+    /// Times the file was read from disk
+    pub reads: usize,
+}
+
+/// A file as it was when it got read
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileVersion {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// Mod files a script handed to the game was built from
+#[derive(Debug, Clone)]
+pub struct Injection {
+    /// File stem of the script, e.g. `sol_ef`
+    pub script: String,
+    pub files: Vec<FileVersion>,
+}
+
+#[derive(Debug, Default)]
+pub struct ModCache {
+    files: BTreeMap<PathBuf, CachedFile>,
+    /// Versions read since `start_recording`, `None` when not recording
+    recorded: Option<Vec<FileVersion>>,
+    /// Last injection into each slot, e.g. `Player 1 Main`
+    injections: BTreeMap<String, Injection>,
+}
+
+impl ModCache {
+    /// Contents of a file, reread only if it changed since the last call.
+    /// `None` if the file can't be read, it's dropped from the cache then.
+    pub fn read(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let metadata =
+            fs::metadata(path).and_then(|metadata| Ok((metadata.modified()?, metadata.len())));
+
+        let (modified, size) = match metadata {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.files.remove(path);
+                return None;
+            }
+        };
+
+        if let Some(cached) = self.files.get(path) {
+            if cached.modified == modified && cached.size == size {
+                let data = cached.data.clone();
+                self.record(path);
+                return Some(data);
+            }
+        }
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Could not read `{}`: {}", path.display(), e);
+                self.files.remove(path);
+                return None;
+            }
+        };
+        debug!("Read `{}` from disk", path.display());
+
+        let reads = self.files.get(path).map_or(0, |cached| cached.reads);
+
+        self.files.insert(
+            path.to_path_buf(),
+            CachedFile {
+                modified,
+                size,
+                hash: bbscript::content_hash(&data),
+                data: data.clone(),
+                reads: reads + 1,
+            },
+        );
+        self.record(path);
+
+        Some(data)
+    }
+
+    /// Starts keeping the version of every file read until `stop_recording`
+    pub fn start_recording(&mut self) {
+        self.recorded = Some(Vec::new());
+    }
+
+    pub fn stop_recording(&mut self) -> Vec<FileVersion> {
+        self.recorded.take().unwrap_or_default()
+    }
+
+    fn record(&mut self, path: &Path) {
+        if let (Some(recorded), Some(cached)) = (self.recorded.as_mut(), self.files.get(path)) {
+            let version = FileVersion {
+                path: path.to_path_buf(),
+                hash: cached.hash.clone(),
+            };
+
+            if !recorded.contains(&version) {
+                recorded.push(version);
+            }
+        }
+    }
+
+    /// Remembers what went into the script handed to the game for a slot, `None` for vanilla
+    pub fn set_injection(&mut self, slot: String, injection: Option<Injection>) {
+        match injection {
+            Some(injection) => self.injections.insert(slot, injection),
+            None => self.injections.remove(&slot),
+        };
+    }
+
+    /// Slots the game currently has a mod script for, in name order
+    pub fn injections(&self) -> impl Iterator<Item = (&String, &Injection)> {
+        self.injections.iter()
+    }
+
+    /// Cached files in path order
+    pub fn files(&self) -> impl Iterator<Item = (&PathBuf, &CachedFile)> {
+        self.files.iter()
+    }
+
+    /// Drops every cached file, injections stay since the game still has those scripts
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
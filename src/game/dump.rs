@@ -0,0 +1,389 @@
+//! Writes the vanilla scripts the game loads to the dumps folder, so modders
+//! always have a base that matches the installed game version.
+
+use super::bbscript::decompile;
+use super::bbscript::disasm;
+use super::bbscript::document::ScriptDocument;
+use super::bbscript::framedata;
+use super::bbscript::search::{self, Match, Query};
+use super::bbscript::strings;
+use super::bbscript::transplant::{self, ScriptPair, Transplanted};
+use super::bbscript::xref::CrossReferences;
+use super::bbscript::{content_hash, BBScript};
+use super::{file_stem, ScriptFile, ScriptType};
+use crate::global;
+
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    /// `{file stem}:{hash}` of every script already handled this session
+    static ref DUMPED_SCRIPTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Writes `{file stem}.bbscript` plus a `.txt` with its size and hash, the first time a script is seen
+pub(super) fn dump_vanilla(script_file: ScriptFile, file_type: ScriptType, vanilla: &[u8]) {
+    let file_stem = file_stem(script_file, file_type);
+    let hash = content_hash(vanilla);
+
+    if !DUMPED_SCRIPTS
+        .lock()
+        .insert(format!("{}:{}", file_stem, hash))
+    {
+        return;
+    }
+
+    let folder = PathBuf::from(global::DUMPS_FOLDER);
+    let script_path = folder.join(format!("{}.bbscript", file_stem));
+    let info_path = folder.join(format!("{}.bbscript.txt", file_stem));
+
+    // dumps from an earlier session are still good if the game hasn't been updated since
+    if fs::read(&script_path).map_or(false, |existing| existing == vanilla) {
+        debug!("`{}` is already up to date", script_path.display());
+        return;
+    }
+
+    let info = format!("size = {}\nsha256 = {}\n", vanilla.len(), hash);
+
+    let result = fs::create_dir_all(&folder)
+        .and_then(|_| fs::write(&script_path, vanilla))
+        .and_then(|_| fs::write(&info_path, info));
+
+    match result {
+        Ok(_) => info!(
+            "Dumped `{}` ({:#X} bytes, sha256 {})",
+            script_path.display(),
+            vanilla.len(),
+            hash
+        ),
+        Err(e) => error!("Failed to dump `{}`: {}", script_path.display(), e),
+    }
+}
+
+/// Every dumped script as `(file stem, contents)` in name order
+fn read_dumps() -> Vec<(String, Vec<u8>)> {
+    let mut paths = fs::read_dir(global::DUMPS_FOLDER)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "bbscript"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_string_lossy().into_owned();
+            match fs::read(&path) {
+                Ok(script) => Some((stem, script)),
+                Err(e) => {
+                    warn!("Failed to read `{}`: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parses every script in the dumps folder and hands them to `f` as `(file stem, script)`,
+/// scripts that don't parse are skipped
+fn with_parsed_dumps<T>(f: impl FnOnce(&[(&str, &BBScript)]) -> T) -> T {
+    let dumps = read_dumps();
+
+    let scripts = dumps
+        .iter()
+        .filter_map(|(stem, script)| match BBScript::parse(script) {
+            Ok(script) => Some((stem.as_str(), script)),
+            Err(e) => {
+                warn!("Skipping dump `{}`: {}", stem, e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let scripts = scripts
+        .iter()
+        .map(|(stem, script)| (*stem, script))
+        .collect::<Vec<_>>();
+
+    f(&scripts)
+}
+
+/// Writes an export next to the dumps
+fn write_export(name: &str, contents: &str) -> io::Result<()> {
+    let path = Path::new(global::DUMPS_FOLDER).join(name);
+    fs::write(&path, contents)?;
+    info!("Wrote `{}`", path.display());
+    Ok(())
+}
+
+/// Runs a search over every script in the dumps folder
+pub fn search_dumps(query: &Query) -> Vec<Match> {
+    with_parsed_dumps(|scripts| {
+        debug!("Searching {} dumped scripts for {:?}", scripts.len(), query);
+        search::search(scripts, query, &global::OPCODES)
+    })
+}
+
+/// Writes `{file stem}.bbs` for every dumped script, returns how many scripts were exported
+pub fn export_disassembly() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        for (stem, script) in scripts {
+            write_export(
+                &format!("{}.bbs", stem),
+                &disasm::disassemble(script, &global::OPCODES),
+            )?;
+        }
+
+        Ok(scripts.len())
+    })
+}
+
+/// Writes `{file stem}.decompiled.txt` with the pseudo-code of every dumped script,
+/// returns how many scripts were exported
+pub fn export_pseudo_code() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        for (stem, script) in scripts {
+            write_export(
+                &format!("{}.decompiled.txt", stem),
+                &decompile::decompile(script, &global::OPCODES),
+            )?;
+        }
+
+        Ok(scripts.len())
+    })
+}
+
+/// Writes `{file stem}.json` and `.yaml` documents for every dumped script, they load from the
+/// Mods folder like any other script, returns how many scripts were exported
+pub fn export_documents() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        for (stem, script) in scripts {
+            let document = ScriptDocument::from_script(script, &global::OPCODES);
+            let json = document
+                .to_json()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let yaml = document
+                .to_yaml()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+            write_export(&format!("{}.json", stem), &json)?;
+            write_export(&format!("{}.yaml", stem), &yaml)?;
+        }
+
+        Ok(scripts.len())
+    })
+}
+
+/// Writes `{character}.xrefs.dot` and `.xrefs.json` for every dumped character, each graph
+/// covering the character's main and effect scripts plus `cmn` and `cmn_ef`.
+/// Returns how many graphs were written.
+pub fn export_xrefs() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        let characters = scripts
+            .iter()
+            .map(|(stem, _)| *stem)
+            .filter(|stem| !stem.ends_with("_ef"))
+            .collect::<Vec<_>>();
+
+        for character in &characters {
+            let effect = format!("{}_ef", character);
+            let mut names = vec![*character, effect.as_str()];
+            for name in &["cmn", "cmn_ef"] {
+                if !names.contains(name) {
+                    names.push(name);
+                }
+            }
+
+            let graphed = scripts
+                .iter()
+                .filter(|(stem, _)| names.contains(stem))
+                .copied()
+                .collect::<Vec<_>>();
+
+            let xrefs = CrossReferences::build(&graphed, &global::OPCODES);
+            let json = xrefs
+                .to_json()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+            write_export(&format!("{}.xrefs.dot", character), &xrefs.to_dot())?;
+            write_export(&format!("{}.xrefs.json", character), &json)?;
+        }
+
+        Ok(characters.len())
+    })
+}
+
+/// Writes `{file stem}.strings.csv` and `.strings.json` for every dumped script,
+/// returns how many scripts were exported
+pub fn export_strings() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        for (stem, script) in scripts {
+            let extracted = strings::extract(script, &global::OPCODES);
+            let json = strings::to_json(&extracted)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+            write_export(
+                &format!("{}.strings.csv", stem),
+                &strings::to_csv(&extracted),
+            )?;
+            write_export(&format!("{}.strings.json", stem), &json)?;
+        }
+
+        Ok(scripts.len())
+    })
+}
+
+/// Writes `{file stem}.framedata.csv`, `.md` and `.json` for every dumped script with attack
+/// states, returns how many scripts had any
+pub fn export_frame_data() -> io::Result<usize> {
+    with_parsed_dumps(|scripts| {
+        let mut exported = 0;
+
+        for (stem, script) in scripts {
+            let states = framedata::frame_data(script, &global::OPCODES);
+            if states.is_empty() {
+                continue;
+            }
+
+            let json =
+                framedata::to_json(&states).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+            write_export(
+                &format!("{}.framedata.csv", stem),
+                &framedata::to_csv(&states),
+            )?;
+            write_export(
+                &format!("{}.framedata.md", stem),
+                &framedata::to_markdown(&states),
+            )?;
+            write_export(&format!("{}.framedata.json", stem), &json)?;
+            exported += 1;
+        }
+
+        Ok(exported)
+    })
+}
+
+/// Copies `state` from the dumped scripts of `donor` into the dumped scripts of `target`,
+/// e.g. `ky` into `sol`, and writes the result to a `{donor} {state}` folder in the Mods folder.
+/// Functions that clash with the target's get `_{donor}` appended. Every written script gets a
+/// `.base` pin with the hash of the target dump it was built from.
+pub fn transplant_dumps(
+    state: &str,
+    donor: &str,
+    target: &str,
+) -> Result<(PathBuf, Transplanted), String> {
+    for (what, name) in &[("state", state), ("donor", donor), ("target", target)] {
+        check_file_name(name).map_err(|e| format!("Invalid {} `{}`: {}", what, name, e))?;
+    }
+
+    let (donor_ef, target_ef) = (format!("{}_ef", donor), format!("{}_ef", target));
+
+    // effect scripts are only needed if the state spawns objects, `transplant` reports that
+    let donor_main = read_dump(donor)?;
+    let donor_effect = read_dump(&donor_ef).ok();
+    let target_main = read_dump(target)?;
+    let target_effect = read_dump(&target_ef).ok();
+
+    let donor_main = parse_dump(donor, &donor_main)?;
+    let donor_effect = donor_effect
+        .as_deref()
+        .map(|data| parse_dump(&donor_ef, data))
+        .transpose()?;
+    let (target_main_data, target_effect_data) = (&target_main[..], target_effect.as_deref());
+    let target_main = parse_dump(target, target_main_data)?;
+    let target_effect = target_effect_data
+        .map(|data| parse_dump(&target_ef, data))
+        .transpose()?;
+
+    let transplanted = transplant::transplant(
+        state,
+        ScriptPair {
+            main: &donor_main,
+            effect: donor_effect.as_ref(),
+        },
+        ScriptPair {
+            main: &target_main,
+            effect: target_effect.as_ref(),
+        },
+        &format!("_{}", donor),
+        &global::OPCODES,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let folder = Path::new(global::MODS_FOLDER).join(format!("{} {}", donor, state));
+    let write = |path: PathBuf, data: &[u8]| {
+        fs::write(&path, data).map_err(|e| format!("Can't write `{}`: {}", path.display(), e))
+    };
+    // pins the target dump the script was built from, `base` is the dump's contents
+    let write_pinned = |stem: &str, data: &[u8], base: &[u8]| {
+        write(folder.join(format!("{}.bbscript", stem)), data)?;
+        write(
+            folder.join(format!("{}.bbscript.base", stem)),
+            format!("{}\n", content_hash(base)).as_bytes(),
+        )
+    };
+
+    fs::create_dir_all(&folder)
+        .map_err(|e| format!("Can't create `{}`: {}", folder.display(), e))?;
+    write_pinned(target, &transplanted.main, target_main_data)?;
+    if let (Some(effect), Some(base)) = (&transplanted.effect, target_effect_data) {
+        write_pinned(&target_ef, effect, base)?;
+    }
+
+    info!(
+        "Transplanted `{}` from `{}` into `{}` at `{}`",
+        state,
+        donor,
+        target,
+        folder.display()
+    );
+    Ok((folder, transplanted))
+}
+
+fn read_dump(stem: &str) -> Result<Vec<u8>, String> {
+    let path = Path::new(global::DUMPS_FOLDER).join(format!("{}.bbscript", stem));
+    fs::read(&path).map_err(|e| format!("Can't read `{}`: {}", path.display(), e))
+}
+
+fn parse_dump<'a>(stem: &str, data: &'a [u8]) -> Result<BBScript<'a>, String> {
+    BBScript::parse(data).map_err(|e| format!("Can't parse dump `{}`: {}", stem, e))
+}
+
+/// Checks that a name from the UI can be used as part of a file name on Windows
+fn check_file_name(name: &str) -> Result<(), &'static str> {
+    const INVALID: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+    if name.trim().is_empty() {
+        Err("can't be empty")
+    } else if name.contains("..") {
+        Err("can't contain `..`")
+    } else if name.chars().any(|c| INVALID.contains(&c) || c.is_control()) {
+        Err("can't contain path separators or any of `<>:\"|?*`")
+    } else if name.ends_with('.') || name.ends_with(' ') {
+        Err("can't end with a dot or a space")
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        for name in &["NmlAtk5A", "CmnActStand", "ky"] {
+            assert_eq!(check_file_name(name), Ok(()));
+        }
+        for name in &["", " ", "..", "a/b", "a\\b", "C:", "a?", "a.", "a ", "a\nb"] {
+            assert!(check_file_name(name).is_err(), "`{}` was accepted", name);
+        }
+    }
+}
@@ -1,14 +1,19 @@
-use super::{get_script_file, names, offset, types, ScriptFile, ScriptType};
+use super::bbscript::BBScript;
+use super::cache::Injection;
+use super::loader::report;
+use super::pairs::{self, LoadedScript};
+use super::slots::{Identified, Slot, SlotTracker};
+use super::{
+    dump, file_stem, get_script_file, offset, script_file_from_shortname, types, ScriptType,
+};
 use crate::{global, make_fn};
 
-use std::ffi::CStr;
-use std::ptr;
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
-};
+use std::collections::HashMap;
+use std::slice;
+use std::sync::{atomic::Ordering, Arc};
 
 use detour::static_detour;
+use log::Level;
 use parking_lot::Mutex;
 
 static_detour! {
@@ -18,17 +23,11 @@ static_detour! {
 }
 
 lazy_static! {
-    static ref MATCH_SCRIPTS: Arc<Mutex<BBScriptStorage>> = Arc::new(Mutex::new(BBScriptStorage {
-        common: None,
-        common_ef: None,
-        player_1: None,
-        player_2: None,
-        player_1_ef: None,
-        player_2_ef: None
-    }));
-    static ref SCRIPT_LOAD_CALL_COUNTER: AtomicUsize = AtomicUsize::new(0);
-    static ref SCRIPT_LAST_CHARACTER: Arc<Mutex<ScriptFile>> =
-        Arc::new(Mutex::new(ScriptFile::Sol));
+    static ref MATCH_SCRIPTS: Arc<Mutex<BBScriptStorage>> =
+        Arc::new(Mutex::new(BBScriptStorage::default()));
+    static ref SCRIPT_SLOTS: Mutex<SlotTracker> = Mutex::new(SlotTracker::default());
+    /// Main script of the character whose effect script is loaded next
+    static ref LAST_MAIN_SCRIPT: Mutex<Option<LoadedScript>> = Mutex::new(None);
 }
 
 pub unsafe fn init_game_hooks() -> Result<(), detour::Error> {
@@ -57,11 +56,8 @@ pub unsafe fn init_game_hooks() -> Result<(), detour::Error> {
 }
 
 // Hook for the fn that transfers script pointers.
-// This implementation currently will break any modes that load in more than 6 scripts (e.g. MOM mode)
+// Scripts are identified by their contents, see `slots` for how they're matched up
 fn load_script_hook(this: *mut u8, script_ptr: *mut u8, script_size: u32) {
-    // TODO: figure out how to detect which character and script
-    // is being loaded in a non-hacky way, should be a UE3 script function
-
     debug!(
         "this: {:#X}, script_ptr: {:#X}, script_size: {:#X}",
         this as usize,
@@ -70,76 +66,124 @@ fn load_script_hook(this: *mut u8, script_ptr: *mut u8, script_size: u32) {
     );
 
     unsafe {
-        let mut last_script = SCRIPT_LAST_CHARACTER.lock();
-        let count = SCRIPT_LOAD_CALL_COUNTER.fetch_add(1, Ordering::SeqCst) % 6;
-
-        let script = if count == 0 || count == 2 {
-            // player 1/2 main file, we find the char names here
-            let state_count = ptr::read_unaligned::<u32>(script_ptr as *const _);
-            // calculate offset to a specific bbscript function that seems to always contains the characters name
-            let character_str = script_ptr.add((0x8 + ((state_count + 1) * 0x24)) as usize);
-
-            let character_shortname = CStr::from_ptr(character_str as *mut i8);
-
-            debug!("character_shortname: {:?}", character_shortname);
-
-            *last_script = match character_shortname.to_bytes() {
-                names::ANSWER => ScriptFile::Answer,
-                names::AXL => ScriptFile::Axl,
-                names::BAIKEN => ScriptFile::Baiken,
-                names::BEDMAN => ScriptFile::Bedman,
-                names::CHIPP => ScriptFile::Chipp,
-                names::DIZZY => ScriptFile::Dizzy,
-                names::ELPHELT => ScriptFile::Elphelt,
-                names::FAUST => ScriptFile::Faust,
-                names::INO => ScriptFile::Ino,
-                names::JACKO => ScriptFile::Jacko,
-                names::JAM => ScriptFile::Jam,
-                names::JOHNNY => ScriptFile::Johnny,
-                names::KUM => ScriptFile::Kum,
-                names::KY => ScriptFile::Ky,
-                names::LEO => ScriptFile::Leo,
-                names::MAY => ScriptFile::May,
-                names::MILLIA => ScriptFile::Millia,
-                names::POTEMKIN => ScriptFile::Potemkin,
-                names::RAMLETHAL => ScriptFile::Ramlethal,
-                names::RAVEN => ScriptFile::Raven,
-                names::SIN => ScriptFile::Sin,
-                names::SLAYER => ScriptFile::Slayer,
-                names::SOL => ScriptFile::Sol,
-                names::VENOM => ScriptFile::Venom,
-                names::ZATO => ScriptFile::Zato,
-                _ => ScriptFile::Sol,
-            };
-
-            get_script_file(*last_script, ScriptType::Main)
-        } else if count == 1 || count == 3 {
-            // player 1/2 effect file
-            get_script_file(*last_script, ScriptType::Effect)
-        } else if count == 4 {
-            // cmn
-            get_script_file(ScriptFile::Common, ScriptType::Main)
-        } else {
-            // cmn effect
-            get_script_file(ScriptFile::Common, ScriptType::Effect)
+        let vanilla = slice::from_raw_parts(script_ptr as *const u8, script_size as usize);
+
+        // only main scripts have a shortname, effect scripts get matched up with the main script before them.
+        // A known shortname in the right spot also counts in case a game version uses another opcode id for it
+        let character_shortname = match BBScript::parse(vanilla) {
+            Ok(script) => {
+                let shortname = script.character_shortname();
+                let known = shortname.map_or(false, |shortname| {
+                    script_file_from_shortname(shortname).is_some()
+                });
+
+                if script.is_main_script() || known {
+                    Some(shortname.unwrap_or_default())
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                error!("Could not parse vanilla script, leaving it alone: {}", e);
+                return LoadBBScriptHook.call(this, script_ptr, script_size);
+            }
         };
 
+        debug!(
+            "character_shortname: {:?}",
+            character_shortname.map(String::from_utf8_lossy)
+        );
+
+        let identified = match SCRIPT_SLOTS
+            .lock()
+            .identify(character_shortname, script_file_from_shortname)
+        {
+            Some(identified) => identified,
+            None => {
+                warn!("Could not tell which script is being loaded, leaving it alone");
+                return LoadBBScriptHook.call(this, script_ptr, script_size);
+            }
+        };
+        let Identified {
+            slot,
+            script_file,
+            file_type,
+            new_cycle,
+        } = identified;
+
         let mut script_storage = MATCH_SCRIPTS.lock();
 
-        match count {
-            0 => script_storage.player_1 = script,
-            1 => script_storage.player_1_ef = script,
-            2 => script_storage.player_2 = script,
-            3 => script_storage.player_2_ef = script,
-            4 => script_storage.common = script,
-            5 => script_storage.common_ef = script,
-            _ => {}
+        if new_cycle {
+            // messages from the last cycle are stale, its scripts stay stored until
+            // their slot loads again since the game may still be reading them
+            global::MOD_MESSAGES.lock().clear();
         }
 
+        // what the Cache tab lists injections under, e.g. `Player 1 Main`
+        let target = format!("{} {:?}", slot, file_type);
+
+        let script_file = match script_file {
+            Some(script_file) => script_file,
+            None => {
+                // injecting another character's mod into an unknown one crashes the game
+                if let Some(shortname) = character_shortname {
+                    report(
+                        Level::Warn,
+                        slot,
+                        format!(
+                            "Unknown character `{}`, loading its scripts without mods",
+                            String::from_utf8_lossy(shortname)
+                        ),
+                    );
+                }
+
+                global::MOD_CACHE.lock().set_injection(target, None);
+                script_storage.store(slot, file_type, None);
+                return LoadBBScriptHook.call(this, script_ptr, script_size);
+            }
+        };
+
+        debug!(
+            "Loading {} {:?} script for {}",
+            script_file, file_type, slot
+        );
+
+        if global::DUMP_SCRIPTS.load(Ordering::SeqCst) {
+            dump::dump_vanilla(script_file, file_type, vanilla);
+        }
+
+        let script = get_script_file(script_file, file_type, slot.side(), vanilla);
+
         let mods_enabled = global::MODS_ENABLED.load(Ordering::SeqCst);
         debug!("Mods enabled: {}", mods_enabled);
+
+        // what the game ends up with, each effect script gets checked against the main script before it
+        let script = script.filter(|_| mods_enabled);
+        let loaded = LoadedScript {
+            vanilla: vanilla.to_vec(),
+            modded: script.as_ref().map(|script| script.data.clone()),
+            from_mods: script.as_ref().map_or(false, |script| script.from_mods),
+        };
+
+        match file_type {
+            ScriptType::Main => *LAST_MAIN_SCRIPT.lock() = Some(loaded),
+            ScriptType::Effect => {
+                if let Some(main) = LAST_MAIN_SCRIPT.lock().take() {
+                    pairs::check_pair(script_file, &main, &loaded);
+                }
+            }
+        }
+
+        let injection = script.as_ref().map(|script| Injection {
+            script: file_stem(script_file, file_type),
+            files: script.files.clone(),
+        });
+        global::MOD_CACHE.lock().set_injection(target, injection);
+
+        script_storage.store(slot, file_type, script.map(|script| script.data));
+
         if mods_enabled {
-            if let Some((mod_pointer, mod_size)) = script_storage.get_script_ptr(count) {
+            if let Some((mod_pointer, mod_size)) = script_storage.get_script_ptr(slot, file_type) {
                 return LoadBBScriptHook.call(this, mod_pointer, mod_size);
             }
         }
@@ -148,52 +192,24 @@ fn load_script_hook(this: *mut u8, script_ptr: *mut u8, script_size: u32) {
     }
 }
 
+/// Mod scripts handed to the game, kept alive here since the game reads them straight out
+/// of these buffers. A buffer is only replaced once the same slot loads again.
+#[derive(Default)]
 struct BBScriptStorage {
-    pub common: Option<Vec<u8>>,
-    pub common_ef: Option<Vec<u8>>,
-    pub player_1: Option<Vec<u8>>,
-    pub player_2: Option<Vec<u8>>,
-    pub player_1_ef: Option<Vec<u8>>,
-    pub player_2_ef: Option<Vec<u8>>,
+    scripts: HashMap<(Slot, ScriptType), Vec<u8>>,
 }
 
 impl BBScriptStorage {
-    /// Index is the number of times the script function has been called during the current match loading cycle
-    pub fn get_script_ptr(&mut self, index: usize) -> Option<(*mut u8, u32)> {
-        match index {
-            0 => {
-                if let Some(script) = self.player_1.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            1 => {
-                if let Some(script) = self.player_1_ef.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            2 => {
-                if let Some(script) = self.player_2.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            3 => {
-                if let Some(script) = self.player_2_ef.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            4 => {
-                if let Some(script) = self.common.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            5 => {
-                if let Some(script) = self.common_ef.as_mut() {
-                    return Some((script.as_mut_ptr(), script.len() as u32));
-                }
-            }
-            _ => {}
-        }
+    pub fn store(&mut self, slot: Slot, file_type: ScriptType, script: Option<Vec<u8>>) {
+        match script {
+            Some(script) => self.scripts.insert((slot, file_type), script),
+            None => self.scripts.remove(&(slot, file_type)),
+        };
+    }
 
-        None
+    pub fn get_script_ptr(&mut self, slot: Slot, file_type: ScriptType) -> Option<(*mut u8, u32)> {
+        self.scripts
+            .get_mut(&(slot, file_type))
+            .map(|script| (script.as_mut_ptr(), script.len() as u32))
     }
 }
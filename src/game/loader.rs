@@ -0,0 +1,494 @@
+//! Turns the mod files for a script into the buffer handed to the game.
+//!
+//! Mods live either directly in the Mods folder or in a subfolder per mod.
+//! The root folder is read first and subfolders follow in name order, when
+//! several of them change the same script their changes are merged.
+//!
+//! A file can be limited to one side by adding the side before the extension,
+//! e.g. `sol.p1.bbscript`. In each folder a file for the side being loaded is
+//! used in place of the shared file.
+
+use super::bbscript::merge::{self, MergeSource};
+use super::bbscript::opcodes::OpcodeTable;
+use super::bbscript::preprocess::{preprocess, Preprocessed};
+use super::bbscript::{self, asm, document, lint, patch::Patch, validate, BBScript};
+use super::cache::FileVersion;
+use super::tunables::{apply_tunables, read_tunables};
+use super::types::ModMessage;
+use super::{file_stem, ScriptFile, ScriptType};
+use crate::error::ScriptError;
+use crate::global;
+
+use log::Level;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+enum SourceContent {
+    Script(Vec<u8>),
+    Patch(Patch),
+}
+
+/// Extensions of the document formats a script can be written in, tried in this order
+const DOCUMENT_FORMATS: [(&str, DocumentConverter); 2] = [
+    ("json", document::json_to_script),
+    ("yaml", document::yaml_to_script),
+];
+
+type DocumentConverter = fn(&str, &OpcodeTable) -> Result<Vec<u8>, ScriptError>;
+
+struct ModSource {
+    path: PathBuf,
+    content: SourceContent,
+}
+
+/// Script to hand to the game in place of the vanilla one
+pub(super) struct ModScript {
+    pub data: Vec<u8>,
+    /// `false` if it's the vanilla script with only tunables written into it
+    pub from_mods: bool,
+    /// Mod files it was built from, as they were read
+    pub files: Vec<FileVersion>,
+}
+
+/// Loads and validates the mod script for a file, `None` means the vanilla script should be used.
+/// `side` is the side the script is loaded for (e.g. `p1`), if it belongs to one.
+pub(super) fn get_script_file(
+    script_file: ScriptFile,
+    file_type: ScriptType,
+    side: Option<&str>,
+    vanilla: &[u8],
+) -> Option<ModScript> {
+    let file_stem = file_stem(script_file, file_type);
+
+    let vanilla_hash = bbscript::content_hash(vanilla);
+
+    let (sources, files): (Vec<_>, Vec<Vec<_>>) = mod_folders()
+        .iter()
+        .filter_map(|folder| {
+            side_stems(&file_stem, side).iter().find_map(|stem| {
+                let (source, files) = recording(|| read_mod_source(folder, stem));
+                let source = source?;
+
+                if let Some(side) = side.filter(|_| stem != &file_stem) {
+                    report(
+                        Level::Info,
+                        source.path.display(),
+                        format!("Using the {} specific file", side),
+                    );
+                }
+
+                Some((source, files))
+            })
+        })
+        .filter(|(source, _)| check_base_pin(&source.path, &vanilla_hash))
+        .unzip();
+    let mut files = files.concat();
+
+    let (tunables, tunable_files) = recording(|| read_tunables(&file_stem));
+
+    let script = combine_sources(sources, &file_stem, vanilla).and_then(|(name, script)| {
+        check_script(&name, script, &file_stem, side, file_type, vanilla)
+    });
+
+    if tunables.is_empty() {
+        return script.map(|data| ModScript {
+            data,
+            from_mods: true,
+            files,
+        });
+    }
+
+    // tunables work on vanilla scripts too (including when the mod failed to load),
+    // they get a copy to write into
+    let from_mods = script.is_some();
+    if !from_mods {
+        files.clear();
+    }
+    files.extend(tunable_files);
+
+    let mut data = script.unwrap_or_else(|| vanilla.to_vec());
+    apply_tunables(&file_stem, &mut data, tunables);
+
+    Some(ModScript {
+        data,
+        from_mods,
+        files,
+    })
+}
+
+/// Runs `read` and returns the version of every mod file it read
+fn recording<T>(read: impl FnOnce() -> T) -> (T, Vec<FileVersion>) {
+    global::MOD_CACHE.lock().start_recording();
+    let value = read();
+    (value, global::MOD_CACHE.lock().stop_recording())
+}
+
+/// Validates and lints a mod script, `None` if it's too broken to load
+fn check_script(
+    name: &str,
+    script: Vec<u8>,
+    file_stem: &str,
+    side: Option<&str>,
+    file_type: ScriptType,
+    vanilla: &[u8],
+) -> Option<Vec<u8>> {
+    match validate::validate(&script, &global::OPCODES) {
+        Ok(warnings) => {
+            for warning in warnings {
+                report(Level::Warn, name, warning);
+            }
+
+            lint_script(name, &script, file_stem, side, file_type, vanilla);
+
+            Some(script)
+        }
+        Err(e) => {
+            report(
+                Level::Error,
+                name,
+                format!("Failed validation, using vanilla script: {}", e),
+            );
+            None
+        }
+    }
+}
+
+/// Logs lint findings for a mod script, main scripts are checked against the first
+/// full `_ef` script for the same side found in the mod folders and against vanilla
+/// for new states nothing enters. Effect script states are entered from the main
+/// script, so they don't get that check.
+fn lint_script(
+    name: &str,
+    script: &[u8],
+    file_stem: &str,
+    side: Option<&str>,
+    file_type: ScriptType,
+    vanilla: &[u8],
+) {
+    let script = match BBScript::parse(script) {
+        Ok(script) => script,
+        Err(_) => return,
+    };
+
+    let effect = match file_type {
+        ScriptType::Main => mod_folders().iter().find_map(|folder| {
+            side_stems(&format!("{}_ef", file_stem), side)
+                .iter()
+                .find_map(|stem| read_effect_pair(folder, stem))
+        }),
+        ScriptType::Effect => None,
+    };
+    let effect = effect
+        .as_deref()
+        .and_then(|effect| BBScript::parse(effect).ok());
+    let vanilla = match file_type {
+        ScriptType::Main => BBScript::parse(vanilla).ok(),
+        ScriptType::Effect => None,
+    };
+
+    for finding in lint::lint(&script, effect.as_ref(), vanilla.as_ref(), &global::OPCODES) {
+        report(Level::Warn, name, finding.to_string());
+    }
+}
+
+/// Reads the `_ef` script next to a main script without reporting anything, it gets loaded
+/// (and reported on) properly once the game asks for it
+fn read_effect_pair(folder: &Path, effect_stem: &str) -> Option<Vec<u8>> {
+    let binary_path = folder.join(format!("{}.bbscript", effect_stem));
+    if let Some(script) = read_file(&binary_path) {
+        return Some(script);
+    }
+
+    let source_path = folder.join(format!("{}.bbs", effect_stem));
+    if let Some(source) = read_source(&source_path) {
+        return assemble_source(&source_path, &source).ok();
+    }
+
+    DOCUMENT_FORMATS.iter().find_map(|(extension, convert)| {
+        let document = read_source(&folder.join(format!("{}.{}", effect_stem, extension)))?;
+        convert(&document, &global::OPCODES).ok()
+    })
+}
+
+/// File stems to look for in each folder, the side specific one first
+fn side_stems(file_stem: &str, side: Option<&str>) -> Vec<String> {
+    side.map(|side| format!("{}.{}", file_stem, side))
+        .into_iter()
+        .chain(Some(file_stem.to_string()))
+        .collect()
+}
+
+/// The Mods folder itself followed by every subfolder in name order
+pub(super) fn mod_folders() -> Vec<PathBuf> {
+    let root = PathBuf::from(global::MODS_FOLDER);
+
+    let mut subfolders = fs::read_dir(&root)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    subfolders.sort();
+
+    let mut folders = vec![root];
+    folders.append(&mut subfolders);
+    folders
+}
+
+/// Uses a single full script as is, anything else gets merged over vanilla
+fn combine_sources(
+    sources: Vec<ModSource>,
+    file_stem: &str,
+    vanilla: &[u8],
+) -> Option<(String, Vec<u8>)> {
+    match sources.as_slice() {
+        [] => return None,
+        [ModSource {
+            path,
+            content: SourceContent::Script(script),
+        }] => return Some((path.display().to_string(), script.clone())),
+        _ => {}
+    }
+
+    let name = match sources.as_slice() {
+        [source] => source.path.display().to_string(),
+        _ => format!(
+            "{} ({} mods)",
+            Path::new(global::MODS_FOLDER).join(file_stem).display(),
+            sources.len()
+        ),
+    };
+
+    let vanilla = match BBScript::parse(vanilla) {
+        Ok(vanilla) => vanilla,
+        Err(e) => {
+            report(
+                Level::Error,
+                &name,
+                format!("Can't merge over vanilla script: {}", e),
+            );
+            return None;
+        }
+    };
+
+    let merge_sources = sources
+        .into_iter()
+        .filter_map(|source| {
+            let patch = match source.content {
+                SourceContent::Patch(patch) => patch,
+                SourceContent::Script(script) => match BBScript::parse(&script) {
+                    Ok(modded) => Patch::diff(&vanilla, &modded),
+                    Err(e) => {
+                        report(
+                            Level::Error,
+                            source.path.display(),
+                            format!("Skipped: {}", e),
+                        );
+                        return None;
+                    }
+                },
+            };
+
+            Some(MergeSource {
+                name: source.path.display().to_string(),
+                patch,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let merged = merge::merge(&vanilla, &merge_sources)
+        .and_then(|merged| Ok((bbscript::build_script(&merged.functions)?, merged.conflicts)));
+
+    match merged {
+        Ok((script, conflicts)) => {
+            for conflict in conflicts {
+                report(
+                    Level::Warn,
+                    &name,
+                    format!(
+                        "`{}` from `{}` is overridden by `{}`",
+                        conflict.function, conflict.overridden, conflict.winner
+                    ),
+                );
+            }
+
+            debug!("Merged script `{}`", name);
+            Some((name, script))
+        }
+        Err(e) => {
+            report(Level::Error, &name, format!("Failed to merge: {}", e));
+            None
+        }
+    }
+}
+
+/// Looks for a prebuilt `.bbscript` first, then a `.bbs` source that gets assembled in memory,
+/// then a `.json` or `.yaml` document that gets converted in memory, then a `.bbpatch` that gets
+/// applied over the vanilla script
+fn read_mod_source(folder: &Path, file_stem: &str) -> Option<ModSource> {
+    let binary_path = folder.join(format!("{}.bbscript", file_stem));
+
+    if let Some(script) = read_file(&binary_path) {
+        debug!("Got script `{}`", binary_path.display());
+        return Some(ModSource {
+            path: binary_path,
+            content: SourceContent::Script(script),
+        });
+    }
+
+    let source_path = folder.join(format!("{}.bbs", file_stem));
+    if let Some(source) = read_source(&source_path) {
+        return match assemble_source(&source_path, &source) {
+            Ok(script) => {
+                debug!("Assembled script `{}`", source_path.display());
+                Some(ModSource {
+                    path: source_path,
+                    content: SourceContent::Script(script),
+                })
+            }
+            Err(e) => {
+                report(
+                    Level::Error,
+                    source_path.display(),
+                    format!("Failed to assemble: {}", e),
+                );
+                None
+            }
+        };
+    }
+
+    for (extension, convert) in &DOCUMENT_FORMATS {
+        let document_path = folder.join(format!("{}.{}", file_stem, extension));
+        if let Some(document) = read_source(&document_path) {
+            return match convert(&document, &global::OPCODES) {
+                Ok(script) => {
+                    debug!("Converted script `{}`", document_path.display());
+                    Some(ModSource {
+                        path: document_path,
+                        content: SourceContent::Script(script),
+                    })
+                }
+                Err(e) => {
+                    report(
+                        Level::Error,
+                        document_path.display(),
+                        format!("Failed to convert: {}", e),
+                    );
+                    None
+                }
+            };
+        }
+    }
+
+    let patch_path = folder.join(format!("{}.bbpatch", file_stem));
+    let source = read_source(&patch_path)?;
+
+    match parse_patch(&patch_path, &source) {
+        Ok(patch) => {
+            debug!("Got patch `{}`", patch_path.display());
+            Some(ModSource {
+                path: patch_path,
+                content: SourceContent::Patch(patch),
+            })
+        }
+        Err(e) => {
+            report(
+                Level::Error,
+                patch_path.display(),
+                format!("Failed to parse patch: {}", e),
+            );
+            None
+        }
+    }
+}
+
+/// Assembles a `.bbs` source after running it through the preprocessor
+fn assemble_source(path: &Path, source: &str) -> Result<Vec<u8>, ScriptError> {
+    let preprocessed = preprocess_source(path, source)?;
+    asm::assemble(&preprocessed.text, &global::OPCODES).map_err(|e| preprocessed.map_error(e))
+}
+
+fn parse_patch(path: &Path, source: &str) -> Result<Patch, ScriptError> {
+    let preprocessed = preprocess_source(path, source)?;
+    Patch::parse(&preprocessed.text, &global::OPCODES).map_err(|e| preprocessed.map_error(e))
+}
+
+/// Includes are looked up next to the including file first and then in the Mods folder,
+/// so snippets shared between mods can live in one place
+fn preprocess_source(path: &Path, source: &str) -> Result<Preprocessed, ScriptError> {
+    preprocess(source, &path.display().to_string(), |from, include| {
+        let next_to = Path::new(from).parent().map(|folder| folder.join(include));
+        let shared = Path::new(global::MODS_FOLDER).join(include);
+
+        next_to
+            .into_iter()
+            .chain(Some(shared))
+            .find_map(|path| read_source(&path).map(|source| (path.display().to_string(), source)))
+            .ok_or_else(|| format!("Can't find `{}` to include", include))
+    })
+}
+
+/// Mods can pin the vanilla script they were built against with a `{mod file}.base` file holding
+/// its sha256, returns false if the mod should be skipped
+fn check_base_pin(path: &Path, vanilla_hash: &str) -> bool {
+    let mut pin_path = path.as_os_str().to_owned();
+    pin_path.push(".base");
+
+    let pin = match read_source(Path::new(&pin_path)) {
+        Some(pin) => pin,
+        None => return true,
+    };
+
+    // accepts plain hashes as well as `sha256sum` output
+    let pinned_hash = pin.split_whitespace().next().unwrap_or_default();
+    if pinned_hash.eq_ignore_ascii_case(vanilla_hash) {
+        return true;
+    }
+
+    if global::STRICT_BASE_PINS.load(Ordering::SeqCst) {
+        report(
+            Level::Error,
+            path.display(),
+            format!(
+                "Built for a different game version (base {}, installed {}), not loading it",
+                pinned_hash, vanilla_hash
+            ),
+        );
+        false
+    } else {
+        report(
+            Level::Warn,
+            path.display(),
+            format!(
+                "Built for a different game version (base {}, installed {})",
+                pinned_hash, vanilla_hash
+            ),
+        );
+        true
+    }
+}
+
+/// Reads a mod file through `global::MOD_CACHE`
+fn read_file(path: &Path) -> Option<Vec<u8>> {
+    global::MOD_CACHE.lock().read(path)
+}
+
+pub(super) fn read_source(path: &Path) -> Option<String> {
+    read_file(path).and_then(|data| String::from_utf8(data).ok())
+}
+
+/// Logs a problem with a mod and keeps it for the Mods tab
+pub(super) fn report(level: Level, source: impl fmt::Display, text: String) {
+    log!(level, "`{}`: {}", source, text);
+
+    global::MOD_MESSAGES.lock().push(ModMessage {
+        level,
+        source: source.to_string(),
+        text,
+    });
+}
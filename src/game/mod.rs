@@ -1,126 +1,83 @@
+pub mod bbscript;
+pub mod cache;
 pub mod hooks;
 pub mod offset;
+pub mod roster;
 pub mod types;
 
-use std::io::prelude::*;
-use std::{fs::File, path::PathBuf};
+mod dump;
+mod loader;
+mod pairs;
+mod slots;
+mod tunables;
 
-// full list of character shortnames used in scripts
-mod names {
-    pub const ANSWER: &[u8] = b"ans";
-    pub const AXL: &[u8] = b"axl";
-    pub const BAIKEN: &[u8] = b"bkn";
-    pub const BEDMAN: &[u8] = b"bed";
-    pub const CHIPP: &[u8] = b"chp";
-    pub const DIZZY: &[u8] = b"dzy";
-    pub const ELPHELT: &[u8] = b"elp";
-    pub const FAUST: &[u8] = b"fau";
-    pub const INO: &[u8] = b"ino";
-    pub const JAM: &[u8] = b"jam";
-    pub const JOHNNY: &[u8] = b"jhn";
-    pub const JACKO: &[u8] = b"jko";
-    pub const KUM: &[u8] = b"kum";
-    pub const KY: &[u8] = b"kyk";
-    pub const LEO: &[u8] = b"leo";
-    pub const MAY: &[u8] = b"may";
-    pub const MILLIA: &[u8] = b"mll";
-    pub const POTEMKIN: &[u8] = b"pot";
-    pub const RAMLETHAL: &[u8] = b"ram";
-    pub const RAVEN: &[u8] = b"rvn";
-    pub const SIN: &[u8] = b"sin";
-    pub const SLAYER: &[u8] = b"sly";
-    pub const SOL: &[u8] = b"sol";
-    pub const VENOM: &[u8] = b"ven";
-    pub const ZATO: &[u8] = b"zat";
-}
+pub use dump::{
+    export_disassembly, export_documents, export_frame_data, export_pseudo_code, export_strings,
+    export_xrefs, search_dumps, transplant_dumps,
+};
+use loader::get_script_file;
+use roster::{Character, COMMON_SHORTNAME};
+
+use crate::global;
+
+use std::fmt;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ScriptFile {
-    Answer,
-    Axl,
-    Baiken,
-    Bedman,
-    Chipp,
-    Dizzy,
-    Elphelt,
-    Faust,
-    Ino,
-    Jam,
-    Johnny,
-    Jacko,
-    Kum,
-    Ky,
-    Leo,
-    May,
-    Millia,
-    Potemkin,
-    Ramlethal,
-    Raven,
-    Sin,
-    Slayer,
-    Sol,
-    Venom,
-    Zato,
+    /// Index into `global::ROSTER`
+    Character(usize),
     Common,
 }
 
 impl ScriptFile {
-    pub fn short_name(&self) -> &str {
+    /// Name shown in the UI
+    pub fn display_name(&self) -> &'static str {
         match self {
-            ScriptFile::Answer => "ans",
-            ScriptFile::Axl => "axl",
-            ScriptFile::Baiken => "bkn",
-            ScriptFile::Bedman => "bed",
-            ScriptFile::Chipp => "chp",
-            ScriptFile::Dizzy => "dzy",
-            ScriptFile::Elphelt => "elp",
-            ScriptFile::Faust => "fau",
-            ScriptFile::Ino => "ino",
-            ScriptFile::Jam => "jam",
-            ScriptFile::Johnny => "jhn",
-            ScriptFile::Jacko => "jko",
-            ScriptFile::Kum => "kum",
-            ScriptFile::Ky => "kyk",
-            ScriptFile::Leo => "leo",
-            ScriptFile::May => "may",
-            ScriptFile::Millia => "mll",
-            ScriptFile::Potemkin => "pot",
-            ScriptFile::Ramlethal => "ram",
-            ScriptFile::Raven => "rvn",
-            ScriptFile::Sin => "sin",
-            ScriptFile::Slayer => "sly",
-            ScriptFile::Sol => "sol",
-            ScriptFile::Venom => "ven",
-            ScriptFile::Zato => "zat",
-            ScriptFile::Common => "cmn",
+            ScriptFile::Character(index) => &Self::character(*index).name,
+            ScriptFile::Common => "Common",
         }
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ScriptType {
-    Main,
-    Effect,
-}
+    /// Start of the file names used for this script in the Mods and dumps folders
+    pub fn file_prefix(&self) -> &'static str {
+        match self {
+            ScriptFile::Character(index) => Self::character(*index).file_prefix(),
+            ScriptFile::Common => COMMON_SHORTNAME,
+        }
+    }
 
-fn get_script_file(script_file: ScriptFile, file_type: ScriptType) -> Option<Vec<u8>> {
-    let mut mods_path = PathBuf::from(crate::global::MODS_FOLDER);
+    fn character(index: usize) -> &'static Character {
+        global::ROSTER
+            .get(index)
+            .expect("Script file points past the end of the roster")
+    }
+}
 
-    let mut script = Vec::new();
+impl fmt::Display for ScriptFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
 
-    let file_name = match file_type {
-        ScriptType::Main => format!("{}.bbscript", script_file.short_name()),
-        ScriptType::Effect => format!("{}_ef.bbscript", script_file.short_name()),
-    };
+/// Script a shortname read out of a main script belongs to
+fn script_file_from_shortname(shortname: &[u8]) -> Option<ScriptFile> {
+    if shortname == COMMON_SHORTNAME.as_bytes() {
+        return Some(ScriptFile::Common);
+    }
 
-    mods_path.push(file_name);
+    global::ROSTER.find(shortname).map(ScriptFile::Character)
+}
 
-    let result = File::open(&mods_path).and_then(|mut file| file.read_to_end(&mut script));
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ScriptType {
+    Main,
+    Effect,
+}
 
-    if result.is_ok() {
-        debug!("Got script `{}`", mods_path.display());
-        Some(script)
-    } else {
-        None
+/// File name without extension used for a script in the Mods and dumps folders, e.g. `sol_ef`
+fn file_stem(script_file: ScriptFile, file_type: ScriptType) -> String {
+    match file_type {
+        ScriptType::Main => script_file.file_prefix().to_string(),
+        ScriptType::Effect => format!("{}_ef", script_file.file_prefix()),
     }
 }
@@ -0,0 +1,78 @@
+//! Checks the main and effect scripts the game actually got for a character against each other.
+
+use super::bbscript::{pair, BBScript};
+use super::loader::report;
+use super::{file_stem, ScriptFile, ScriptType};
+use crate::global;
+
+use log::Level;
+
+/// A script as the game got it, `modded` is `None` if it got the vanilla script
+pub(super) struct LoadedScript {
+    pub vanilla: Vec<u8>,
+    pub modded: Option<Vec<u8>>,
+    /// `modded` came from mod files, not just tunables applied to the vanilla script
+    pub from_mods: bool,
+}
+
+impl LoadedScript {
+    fn used(&self) -> &[u8] {
+        self.modded.as_deref().unwrap_or(&self.vanilla)
+    }
+}
+
+/// Reports objects the loaded pair is missing and pairs that mix modded and vanilla scripts.
+/// Objects vanilla doesn't have either (e.g. ones from the common effect script) are ignored.
+pub(super) fn check_pair(script_file: ScriptFile, main: &LoadedScript, effect: &LoadedScript) {
+    // tunables only change values, they can't break a pair
+    if !main.from_mods && !effect.from_mods {
+        return;
+    }
+
+    let name = file_stem(script_file, ScriptType::Main);
+
+    let parse = |data| match BBScript::parse(data) {
+        Ok(script) => Some(script),
+        Err(e) => {
+            error!("Can't check `{}` pair: {}", name, e);
+            None
+        }
+    };
+
+    let (used_main, used_effect, vanilla_main, vanilla_effect) = match (
+        parse(main.used()),
+        parse(effect.used()),
+        parse(&main.vanilla),
+        parse(&effect.vanilla),
+    ) {
+        (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+        _ => return,
+    };
+
+    match (main.from_mods, effect.from_mods) {
+        (true, false) => report(
+            Level::Info,
+            &name,
+            "Modded main script is paired with the vanilla effect script".into(),
+        ),
+        (false, true) => report(
+            Level::Info,
+            &name,
+            "Vanilla main script is paired with a modded effect script".into(),
+        ),
+        _ => {}
+    }
+
+    let vanilla_missing = pair::missing_objects(&vanilla_main, &vanilla_effect, &global::OPCODES);
+
+    for missing in pair::missing_objects(&used_main, &used_effect, &global::OPCODES) {
+        if vanilla_missing
+            .iter()
+            .any(|vanilla| vanilla.object == missing.object)
+        {
+            continue;
+        }
+
+        report(Level::Warn, &name, missing.to_string());
+    }
+}
@@ -0,0 +1,246 @@
+//! Characters the mod knows about, matched by the shortname in their main script.
+//!
+//! The built in list comes from `roster.json` in this folder. A `roster.json`
+//! next to the DLL in the same format is layered over it, entries with a
+//! shortname that's already in the list replace it and new ones are added,
+//! so a new character doesn't need a rebuild.
+
+use crate::error::ModError;
+
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Shortname of the common scripts every match loads, can't be used by a character
+pub const COMMON_SHORTNAME: &str = "cmn";
+
+/// Roster compiled into the DLL, used when there's no external roster or it fails to load
+const BUILTIN_ROSTER: &str = include_str!("roster.json");
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Character {
+    /// Name the character's main script declares itself with, e.g. `sol`
+    pub shortname: String,
+    /// Name shown in the UI
+    pub name: String,
+    /// Start of the names of this character's mod and dump files, the shortname if not given
+    #[serde(default)]
+    file_prefix: Option<String>,
+}
+
+impl Character {
+    pub fn file_prefix(&self) -> &str {
+        self.file_prefix.as_deref().unwrap_or(&self.shortname)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RosterFile {
+    characters: Vec<Character>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Roster {
+    characters: Vec<Character>,
+}
+
+impl Roster {
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_ROSTER).expect("Built in roster is invalid")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ModError> {
+        let file: RosterFile =
+            serde_json::from_str(json).map_err(|e| ModError::InvalidRoster(e.to_string()))?;
+
+        let mut roster = Self::default();
+        for character in file.characters {
+            if character.shortname.is_empty() || character.shortname == COMMON_SHORTNAME {
+                return Err(ModError::InvalidRoster(format!(
+                    "`{}` can't be used as a shortname",
+                    character.shortname
+                )));
+            }
+
+            if roster.find(character.shortname.as_bytes()).is_some() {
+                return Err(ModError::InvalidRoster(format!(
+                    "`{}` is in the roster more than once",
+                    character.shortname
+                )));
+            }
+
+            roster.characters.push(character);
+        }
+
+        roster.check_prefixes()?;
+        Ok(roster)
+    }
+
+    /// File prefixes name files in the Mods and dumps folders, they can't point elsewhere
+    /// or at another character's (or the common) files
+    fn check_prefixes(&self) -> Result<(), ModError> {
+        for (index, character) in self.characters.iter().enumerate() {
+            let prefix = character.file_prefix();
+
+            // no dots or separators, so it can't leave the folder or look like a side file,
+            // and no `_ef` ending that would match another script's effect file
+            let is_plain_name = !prefix.is_empty()
+                && prefix
+                    .chars()
+                    .all(|c| c == '_' || c == '-' || c.is_ascii_alphanumeric())
+                && !prefix.ends_with("_ef");
+            if !is_plain_name || prefix == COMMON_SHORTNAME {
+                return Err(ModError::InvalidRoster(format!(
+                    "`{}` can't be used as the file prefix of `{}`",
+                    prefix, character.shortname
+                )));
+            }
+
+            if let Some(other) = self.characters[..index]
+                .iter()
+                .find(|other| other.file_prefix() == prefix)
+            {
+                return Err(ModError::InvalidRoster(format!(
+                    "`{}` and `{}` both use the file prefix `{}`",
+                    other.shortname, character.shortname, prefix
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Built in roster with the characters from a roster file layered over it,
+    /// falls back to only the built in roster if the file is missing or broken
+    pub fn load(path: &Path) -> Self {
+        let mut roster = Self::builtin();
+
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(_) => {
+                debug!("No roster at `{}`, using built in roster", path.display());
+                return roster;
+            }
+        };
+
+        match Self::from_json(&json) {
+            Ok(external) => {
+                info!(
+                    "Loaded {} characters from `{}`",
+                    external.characters.len(),
+                    path.display()
+                );
+
+                match roster.layered(external) {
+                    Ok(layered) => roster = layered,
+                    Err(e) => error!("`{}`: {}, using built in roster", path.display(), e),
+                }
+            }
+            Err(e) => error!("`{}`: {}, using built in roster", path.display(), e),
+        }
+
+        roster
+    }
+
+    /// This roster with the characters of `external` replacing the ones with the same shortname
+    /// or added at the end
+    fn layered(&self, external: Roster) -> Result<Self, ModError> {
+        let mut layered = self.clone();
+        for character in external.characters {
+            match layered.find(character.shortname.as_bytes()) {
+                Some(index) => layered.characters[index] = character,
+                None => layered.characters.push(character),
+            }
+        }
+
+        // a prefix can clash with a built in character only once both are in one list
+        layered.check_prefixes()?;
+        Ok(layered)
+    }
+
+    /// Index of the character with a shortname, as read out of a script
+    pub fn find(&self, shortname: &[u8]) -> Option<usize> {
+        self.characters
+            .iter()
+            .position(|character| character.shortname.as_bytes() == shortname)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Character> {
+        self.characters.get(index)
+    }
+
+    pub fn characters(&self) -> &[Character] {
+        &self.characters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roster(characters: &str) -> Result<Roster, ModError> {
+        Roster::from_json(&format!("{{\"characters\": [{}]}}", characters))
+    }
+
+    const BASE: &str = r#"{"shortname": "sol", "name": "Sol"}, {"shortname": "kyk", "name": "Ky"}"#;
+
+    #[test]
+    fn builtin_roster_is_valid() {
+        let roster = Roster::builtin();
+        assert!(roster.find(b"sol").is_some());
+        assert!(roster.find(COMMON_SHORTNAME.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_shortnames() {
+        assert!(roster(r#"{"shortname": "cmn", "name": "Common"}"#).is_err());
+        assert!(roster(r#"{"shortname": "", "name": "Nobody"}"#).is_err());
+        assert!(roster(
+            r#"{"shortname": "sol", "name": "Sol"}, {"shortname": "sol", "name": "Sol again"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_bad_prefixes() {
+        for prefix in &["", "cmn", "sol_ef", "a/b", "a\\b", "..", "a.b", "a b"] {
+            let json = format!(
+                r#"{{"shortname": "new", "name": "New", "file_prefix": "{}"}}"#,
+                prefix
+            );
+            assert!(roster(&json).is_err(), "prefix `{}` was accepted", prefix);
+        }
+
+        let roster =
+            roster(r#"{"shortname": "new", "name": "New", "file_prefix": "new-Char_2"}"#).unwrap();
+        assert_eq!(roster.get(0).unwrap().file_prefix(), "new-Char_2");
+    }
+
+    #[test]
+    fn rejects_shared_prefixes() {
+        assert!(roster(r#"{"shortname": "sol", "name": "Sol"}, {"shortname": "new", "name": "New", "file_prefix": "sol"}"#).is_err());
+    }
+
+    #[test]
+    fn layers_external_rosters() {
+        let base = roster(BASE).unwrap();
+
+        let layered = base
+            .layered(roster(r#"{"shortname": "kyk", "name": "Ky Kiske"}, {"shortname": "new", "name": "New"}"#).unwrap())
+            .unwrap();
+        let names = layered
+            .characters()
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Sol", "Ky Kiske", "New"]);
+
+        // only clashes with the built in characters once both are in one list
+        let clash = roster(r#"{"shortname": "new", "name": "New", "file_prefix": "sol"}"#).unwrap();
+        assert!(base.layered(clash).is_err());
+
+        // replacing a character frees its prefix
+        let moved = roster(r#"{"shortname": "sol", "name": "Sol", "file_prefix": "sol2"}, {"shortname": "new", "name": "New", "file_prefix": "sol"}"#).unwrap();
+        assert!(base.layered(moved).is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+//! Works out which script the game is loading from the script itself instead of the call order.
+//!
+//! Main scripts carry the shortname of their character (or `cmn`), effect scripts
+//! don't and always come right after their main script. A main script with a
+//! shortname that isn't in the roster still takes up a player slot, it and its
+//! effect script are identified as an unknown character. The first character in a
+//! loading cycle is player 1, the next one player 2 and so on, so modes that load
+//! more characters just get more player slots. The common scripts load after the
+//! characters, a cycle ends once the common effect script has loaded or a main
+//! script shows up after the common script.
+
+use super::{ScriptFile, ScriptType};
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Slot {
+    /// Character loaded in this position of the cycle, 0 is player 1
+    Player(usize),
+    Common,
+}
+
+impl Slot {
+    /// Side name used for side specific mod files, `p1` or `p2`.
+    /// Every character after the first one in a cycle is on the player 2 side.
+    pub fn side(&self) -> Option<&'static str> {
+        match self {
+            Slot::Player(0) => Some("p1"),
+            Slot::Player(_) => Some("p2"),
+            Slot::Common => None,
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Slot::Player(index) => write!(f, "Player {}", index + 1),
+            Slot::Common => write!(f, "Common"),
+        }
+    }
+}
+
+/// What a loaded script was identified as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identified {
+    pub slot: Slot,
+    /// `None` for a character that isn't in the roster
+    pub script_file: Option<ScriptFile>,
+    pub file_type: ScriptType,
+    /// First script of a new loading cycle
+    pub new_cycle: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct SlotTracker {
+    /// A cycle has started and the common scripts haven't finished loading yet
+    open: bool,
+    /// Characters loaded so far this cycle
+    players: usize,
+    common_loaded: bool,
+    /// Main script the next effect script belongs to
+    last_main: Option<(Slot, Option<ScriptFile>)>,
+}
+
+impl SlotTracker {
+    /// Identifies a script from the shortname of a main script, or `None` for an effect script.
+    /// `lookup` finds the script file for a shortname, `None` for characters that aren't in the roster.
+    /// Returns `None` for an effect script without a main script before it.
+    pub fn identify(
+        &mut self,
+        shortname: Option<&[u8]>,
+        lookup: impl Fn(&[u8]) -> Option<ScriptFile>,
+    ) -> Option<Identified> {
+        let script_file = shortname.map(lookup);
+
+        // only a main script can start a cycle, a stray effect script belongs to the last one
+        let new_cycle = script_file.is_some() && (!self.open || self.common_loaded);
+        if new_cycle {
+            debug!("New script loading cycle");
+            *self = Self {
+                open: true,
+                ..Self::default()
+            };
+        }
+
+        let (slot, script_file, file_type) = match script_file {
+            Some(Some(ScriptFile::Common)) => {
+                self.common_loaded = true;
+                self.last_main = Some((Slot::Common, Some(ScriptFile::Common)));
+                (Slot::Common, Some(ScriptFile::Common), ScriptType::Main)
+            }
+            Some(script_file) => {
+                let slot = Slot::Player(self.players);
+                self.players += 1;
+                self.last_main = Some((slot, script_file));
+                (slot, script_file, ScriptType::Main)
+            }
+            // an effect script belongs to the main script right before it, and only to that one
+            None => {
+                let (slot, script_file) = self.last_main.take()?;
+                if slot == Slot::Common {
+                    self.open = false;
+                }
+                (slot, script_file, ScriptType::Effect)
+            }
+        };
+
+        Some(Identified {
+            slot,
+            script_file,
+            file_type,
+            new_cycle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: ScriptFile = ScriptFile::Character(0);
+    const KY: ScriptFile = ScriptFile::Character(1);
+    const EFFECT: Option<&[u8]> = None;
+
+    /// Name, shortnames of the loaded scripts (`None` for effect scripts) and what they should be identified as
+    type Case = (
+        &'static str,
+        Vec<Option<&'static [u8]>>,
+        Vec<Option<Identified>>,
+    );
+
+    fn lookup(shortname: &[u8]) -> Option<ScriptFile> {
+        match shortname {
+            b"sol" => Some(SOL),
+            b"ky" => Some(KY),
+            b"cmn" => Some(ScriptFile::Common),
+            _ => None,
+        }
+    }
+
+    /// Shorthand for the expected result of a main script
+    fn main(slot: Slot, script_file: Option<ScriptFile>, new_cycle: bool) -> Option<Identified> {
+        Some(Identified {
+            slot,
+            script_file,
+            file_type: ScriptType::Main,
+            new_cycle,
+        })
+    }
+
+    fn effect(slot: Slot, script_file: Option<ScriptFile>) -> Option<Identified> {
+        Some(Identified {
+            slot,
+            script_file,
+            file_type: ScriptType::Effect,
+            new_cycle: false,
+        })
+    }
+
+    #[test]
+    fn identifies_loading_cycles() {
+        let (p1, p2, p3) = (Slot::Player(0), Slot::Player(1), Slot::Player(2));
+        let cases: Vec<Case> = vec![
+            (
+                "two characters",
+                vec![
+                    Some(b"sol"),
+                    EFFECT,
+                    Some(b"ky"),
+                    EFFECT,
+                    Some(b"cmn"),
+                    EFFECT,
+                ],
+                vec![
+                    main(p1, Some(SOL), true),
+                    effect(p1, Some(SOL)),
+                    main(p2, Some(KY), false),
+                    effect(p2, Some(KY)),
+                    main(Slot::Common, Some(ScriptFile::Common), false),
+                    effect(Slot::Common, Some(ScriptFile::Common)),
+                ],
+            ),
+            (
+                "extra characters",
+                vec![
+                    Some(b"sol"),
+                    EFFECT,
+                    Some(b"ky"),
+                    EFFECT,
+                    Some(b"sol"),
+                    EFFECT,
+                    Some(b"cmn"),
+                ],
+                vec![
+                    main(p1, Some(SOL), true),
+                    effect(p1, Some(SOL)),
+                    main(p2, Some(KY), false),
+                    effect(p2, Some(KY)),
+                    main(p3, Some(SOL), false),
+                    effect(p3, Some(SOL)),
+                    main(Slot::Common, Some(ScriptFile::Common), false),
+                ],
+            ),
+            (
+                "no common effect script",
+                vec![Some(b"sol"), EFFECT, Some(b"cmn"), Some(b"ky"), EFFECT],
+                vec![
+                    main(p1, Some(SOL), true),
+                    effect(p1, Some(SOL)),
+                    main(Slot::Common, Some(ScriptFile::Common), false),
+                    main(p1, Some(KY), true),
+                    effect(p1, Some(KY)),
+                ],
+            ),
+            (
+                "unknown character",
+                vec![
+                    Some(b"sol"),
+                    EFFECT,
+                    Some(b"new"),
+                    EFFECT,
+                    Some(b"cmn"),
+                    EFFECT,
+                ],
+                vec![
+                    main(p1, Some(SOL), true),
+                    effect(p1, Some(SOL)),
+                    main(p2, None, false),
+                    effect(p2, None),
+                    main(Slot::Common, Some(ScriptFile::Common), false),
+                    effect(Slot::Common, Some(ScriptFile::Common)),
+                ],
+            ),
+            (
+                "stray effect scripts",
+                vec![EFFECT, Some(b"sol"), EFFECT, EFFECT],
+                vec![None, main(p1, Some(SOL), true), effect(p1, Some(SOL)), None],
+            ),
+        ];
+
+        for (name, loads, expected) in cases {
+            let mut tracker = SlotTracker::default();
+            let identified = loads
+                .into_iter()
+                .map(|shortname| tracker.identify(shortname, lookup))
+                .collect::<Vec<_>>();
+
+            assert_eq!(identified, expected, "{}", name);
+        }
+    }
+}
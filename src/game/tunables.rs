@@ -0,0 +1,177 @@
+//! Named numeric parameters mods expose so they can be changed without rebuilding the script.
+//!
+//! A mod declares them in `{file stem}.tunables.json` next to its script:
+//!
+//! ```json
+//! {
+//!   "tunables": [
+//!     { "name": "5A damage", "function": "NmlAtk5A", "instruction": 3, "arg": 0, "min": 0, "max": 100, "default": 30 }
+//!   ]
+//! }
+//! ```
+//!
+//! `instruction` counts instructions from the start of the function and `arg` is
+//! the index of an integer argument of that instruction. Values picked in the Mods
+//! tab get written into the script the next time the game loads it.
+
+use super::bbscript::disasm::{instructions, Item};
+use super::bbscript::opcodes::{ArgType, OPCODE_SIZE};
+use super::bbscript::BBScript;
+use super::loader::{mod_folders, read_source, report};
+use super::types::Tunable;
+use crate::global;
+
+use log::Level;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize)]
+struct TunableFile {
+    tunables: Vec<TunableDecl>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct TunableDecl {
+    name: String,
+    function: String,
+    instruction: usize,
+    arg: usize,
+    min: i32,
+    max: i32,
+    default: i32,
+}
+
+/// Every tunable declared for a script across all mod folders, with the file it came from
+pub(super) fn read_tunables(file_stem: &str) -> Vec<(PathBuf, TunableDecl)> {
+    let mut decls = Vec::new();
+
+    for folder in mod_folders() {
+        let path = folder.join(format!("{}.tunables.json", file_stem));
+        let json = match read_source(&path) {
+            Some(json) => json,
+            None => continue,
+        };
+
+        match serde_json::from_str::<TunableFile>(&json) {
+            Ok(file) => {
+                debug!(
+                    "Got {} tunables from `{}`",
+                    file.tunables.len(),
+                    path.display()
+                );
+                decls.extend(file.tunables.into_iter().map(|decl| (path.clone(), decl)));
+            }
+            Err(e) => report(
+                Level::Error,
+                path.display(),
+                format!("Failed to read tunables: {}", e),
+            ),
+        }
+    }
+
+    decls
+}
+
+/// Writes the current value of every tunable into the script and updates the list shown in the UI
+pub(super) fn apply_tunables(
+    file_stem: &str,
+    script: &mut [u8],
+    decls: Vec<(PathBuf, TunableDecl)>,
+) {
+    let mut all_tunables = global::TUNABLES.lock();
+    let mut tunables = Vec::with_capacity(decls.len());
+
+    for (path, decl) in decls {
+        if decl.min > decl.max || decl.default < decl.min || decl.default > decl.max {
+            report(
+                Level::Error,
+                path.display(),
+                format!("`{}` has a default outside of its range", decl.name),
+            );
+            continue;
+        }
+
+        let offset = match value_offset(script, &decl) {
+            Ok(offset) => offset,
+            Err(e) => {
+                report(
+                    Level::Error,
+                    path.display(),
+                    format!("`{}`: {}", decl.name, e),
+                );
+                continue;
+            }
+        };
+
+        // keep what the user picked last time the script loaded
+        let value = all_tunables
+            .iter()
+            .find(|tunable| tunable.script == file_stem && tunable.name == decl.name)
+            .map_or(decl.default, |tunable| tunable.value)
+            .max(decl.min)
+            .min(decl.max);
+
+        script[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        debug!("Set `{}` of `{}` to {}", decl.name, file_stem, value);
+
+        tunables.push(Tunable {
+            script: file_stem.to_string(),
+            name: decl.name,
+            min: decl.min,
+            max: decl.max,
+            default: decl.default,
+            value,
+        });
+    }
+
+    all_tunables.retain(|tunable| tunable.script != file_stem);
+    all_tunables.append(&mut tunables);
+}
+
+/// Offset from the start of the script of the argument a tunable points at
+fn value_offset(script: &[u8], decl: &TunableDecl) -> Result<usize, String> {
+    let parsed = BBScript::parse(script).map_err(|e| e.to_string())?;
+    let function = parsed
+        .function(&decl.function)
+        .ok_or_else(|| format!("no function named `{}`", decl.function))?;
+
+    let instruction = match instructions(parsed.function_body(function), &global::OPCODES)
+        .nth(decl.instruction)
+    {
+        Some(Item::Instruction(instruction)) => instruction,
+        Some(_) => {
+            return Err(format!(
+                "instruction {} of `{}` can't be decoded",
+                decl.instruction, decl.function
+            ))
+        }
+        None => {
+            return Err(format!(
+                "`{}` has no instruction {}",
+                decl.function, decl.instruction
+            ))
+        }
+    };
+
+    let opcode = instruction.opcode;
+    match opcode.args.get(decl.arg) {
+        Some(arg) if arg.ty == ArgType::Int => {}
+        _ => {
+            return Err(format!(
+                "`{}` has no integer argument {}",
+                opcode.name, decl.arg
+            ))
+        }
+    }
+
+    let arg_offset = opcode.args[..decl.arg]
+        .iter()
+        .map(|arg| arg.ty.size())
+        .sum::<usize>();
+
+    Ok(parsed.code_start()
+        + function.offset as usize
+        + instruction.offset
+        + OPCODE_SIZE
+        + arg_offset)
+}
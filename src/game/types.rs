@@ -13,3 +13,23 @@ pub struct GameState {
 }
 
 unsafe impl Send for GameState {}
+
+/// Something that happened while loading a mod, shown in the Mods tab
+#[derive(Debug, Clone)]
+pub struct ModMessage {
+    pub level: log::Level,
+    pub source: String,
+    pub text: String,
+}
+
+/// A named script value a mod lets the user change, applied the next time the script loads
+#[derive(Debug, Clone)]
+pub struct Tunable {
+    /// File stem of the script it belongs to, e.g. `sol`
+    pub script: String,
+    pub name: String,
+    pub min: i32,
+    pub max: i32,
+    pub default: i32,
+    pub value: i32,
+}
@@ -1,7 +1,11 @@
-use crate::game::types::GameState;
+use crate::game::bbscript::opcodes::OpcodeTable;
+use crate::game::cache::ModCache;
+use crate::game::roster::Roster;
+use crate::game::types::{GameState, ModMessage, Tunable};
 
 use parking_lot::Mutex;
 
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU32};
 use std::sync::Arc;
 
@@ -9,8 +13,31 @@ lazy_static! {
     /// Base EXE eddress in memory, used for adding with function offsets
     pub static ref BASE_ADDRESS: AtomicU32 = AtomicU32::new(0);
     pub static ref MODS_ENABLED: AtomicBool = AtomicBool::new(true);
+    /// Refuse mods whose `.base` pin doesn't match the vanilla script instead of just warning
+    pub static ref STRICT_BASE_PINS: AtomicBool = AtomicBool::new(true);
+    /// Write every vanilla script the game loads to `DUMPS_FOLDER`
+    pub static ref DUMP_SCRIPTS: AtomicBool = AtomicBool::new(false);
     pub static ref SAVED_GAME_STATE: Arc<Mutex<Option<GameState>>> = Arc::new(Mutex::new(None));
+    /// Messages from the current match loading cycle
+    pub static ref MOD_MESSAGES: Arc<Mutex<Vec<ModMessage>>> = Arc::new(Mutex::new(Vec::new()));
+    /// Tunables of every script loaded this session, values the user picked are kept across loads
+    pub static ref TUNABLES: Arc<Mutex<Vec<Tunable>>> = Arc::new(Mutex::new(Vec::new()));
+    /// Every mod file read this session, only reread from disk once it changes
+    pub static ref MOD_CACHE: Arc<Mutex<ModCache>> = Arc::new(Mutex::new(ModCache::default()));
+    /// Opcode layouts used to assemble and inspect scripts
+    pub static ref OPCODES: OpcodeTable = OpcodeTable::load(Path::new(OPCODES_FILE));
+    /// Characters whose scripts can be modded
+    pub static ref ROSTER: Roster = Roster::load(Path::new(ROSTER_FILE));
 }
 
-/// The folder where all .bbscript files are held
+/// The folder where all mod scripts (.bbscript, .bbs, .json, .bbpatch) and their sidecar files are held
 pub const MODS_FOLDER: &str = r"..\..\Mods";
+
+/// Opcode table layered over the built in one, relative to the game executable like the log file
+pub const OPCODES_FILE: &str = "opcodes.json";
+
+/// Character roster layered over the built in one, next to `OPCODES_FILE`
+pub const ROSTER_FILE: &str = "roster.json";
+
+/// The folder vanilla scripts get dumped to, kept outside of the Mods folder so dumps never get loaded as mods
+pub const DUMPS_FOLDER: &str = r"..\..\dumps";
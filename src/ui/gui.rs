@@ -1,9 +1,17 @@
+use crate::game::bbscript::search::{Query, QueryKind};
+use crate::game::{
+    export_disassembly, export_documents, export_frame_data, export_pseudo_code, export_strings,
+    export_xrefs, search_dumps, transplant_dumps,
+};
 use crate::global;
 
 use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
 use std::sync::Arc;
+use std::thread;
 
 use imgui::*;
+use log::Level;
 use parking_lot::Mutex;
 use winapi::um::winuser::*;
 
@@ -15,6 +23,15 @@ lazy_static! {
         GuiState {
             // define defaults for UI here
             display_ui: true,
+            search_kind: QueryKind::Opcode,
+            search_text: ImString::with_capacity(64),
+            search_results: Vec::new(),
+            search_task: None,
+            export_status: String::new(),
+            export_task: None,
+            transplant_state: ImString::with_capacity(32),
+            transplant_donor: ImString::with_capacity(32),
+            transplant_target: ImString::with_capacity(32),
         }
     ));
 }
@@ -26,6 +43,18 @@ pub fn ui_loop(ui: Ui) -> Ui {
         ui_state.display_ui = !ui_state.display_ui;
     }
 
+    if let Some(results) = poll_task(&mut ui_state.search_task, || {
+        vec!["Search failed, see the log".into()]
+    }) {
+        ui_state.search_results = results;
+    }
+
+    if let Some(status) = poll_task(&mut ui_state.export_task, || {
+        "Export failed, see the log".into()
+    }) {
+        ui_state.export_status = status;
+    }
+
     if ui_state.display_ui {
         Window::new(im_str!("Rev2 Mod"))
             .size([200., 400.], Condition::Once)
@@ -38,6 +67,211 @@ pub fn ui_loop(ui: Ui) -> Ui {
                             debug!("Storing {} in global::MODS_ENABLED", mods_on);
                             global::MODS_ENABLED.store(mods_on, Ordering::SeqCst)
                         };
+
+                        let mut strict_pins = global::STRICT_BASE_PINS.load(Ordering::SeqCst);
+
+                        if ui.checkbox(im_str!("Refuse Mods For Other Versions"), &mut strict_pins) {
+                            debug!("Storing {} in global::STRICT_BASE_PINS", strict_pins);
+                            global::STRICT_BASE_PINS.store(strict_pins, Ordering::SeqCst)
+                        };
+
+                        let mut dump_on = global::DUMP_SCRIPTS.load(Ordering::SeqCst);
+
+                        if ui.checkbox(im_str!("Dump Vanilla Scripts"), &mut dump_on) {
+                            debug!("Storing {} in global::DUMP_SCRIPTS", dump_on);
+                            global::DUMP_SCRIPTS.store(dump_on, Ordering::SeqCst)
+                        };
+
+                        let mut tunables = global::TUNABLES.lock();
+                        if !tunables.is_empty() {
+                            ui.separator();
+                            ui.text("Tunables (used the next time scripts load)");
+                        }
+
+                        for tunable in tunables.iter_mut() {
+                            let label = ImString::new(format!("{}##{}", tunable.name, tunable.script));
+
+                            if Slider::new(&label)
+                                .range(tunable.min..=tunable.max)
+                                .build(&ui, &mut tunable.value)
+                            {
+                                debug!("Set tunable `{}` of `{}` to {}", tunable.name, tunable.script, tunable.value);
+                            }
+
+                            if ui.is_item_hovered() {
+                                ui.tooltip_text(format!("{}, default {}", tunable.script, tunable.default));
+                            }
+                        }
+
+                        let messages = global::MOD_MESSAGES.lock();
+                        if !messages.is_empty() {
+                            ui.separator();
+                        }
+
+                        for message in messages.iter() {
+                            let color = match message.level {
+                                Level::Error => [1.0, 0.4, 0.4, 1.0],
+                                Level::Warn => [1.0, 0.8, 0.3, 1.0],
+                                _ => [1.0, 1.0, 1.0, 1.0],
+                            };
+
+                            ui.text_colored(color, format!("{}: {}", message.source, message.text));
+                        }
+                    });
+
+                    TabItem::new(im_str!("Search")).build(&ui, || {
+                        ui.radio_button(im_str!("Opcode"), &mut ui_state.search_kind, QueryKind::Opcode);
+                        ui.same_line(0.0);
+                        ui.radio_button(im_str!("String"), &mut ui_state.search_kind, QueryKind::String);
+                        ui.same_line(0.0);
+                        ui.radio_button(im_str!("Value"), &mut ui_state.search_kind, QueryKind::Value);
+
+                        ui.input_text(im_str!("##search_text"), &mut ui_state.search_text)
+                            .build();
+                        ui.same_line(0.0);
+
+                        if ui.small_button(im_str!("Search")) && ui_state.search_task.is_none() {
+                            match Query::new(ui_state.search_kind, ui_state.search_text.to_str()) {
+                                Ok(query) => {
+                                    ui_state.search_task = Some(run_in_background(move || {
+                                        let matches = search_dumps(&query);
+                                        let mut results = vec![format!("{} matches", matches.len())];
+                                        results.extend(matches.iter().map(|m| m.to_string()));
+                                        results
+                                    }));
+                                }
+                                Err(e) => ui_state.search_results = vec![e],
+                            }
+                        }
+
+                        if ui_state.search_task.is_some() {
+                            ui.text(im_str!("Searching..."));
+                        } else if ui_state.search_results.is_empty() {
+                            ui.text_wrapped(im_str!("Searches every script in the dumps folder, turn on \"Dump Vanilla Scripts\" and load some matches to fill it"));
+                        }
+
+                        for result in ui_state.search_results.iter().take(MAX_SEARCH_RESULTS) {
+                            ui.text(result);
+                        }
+                    });
+
+                    TabItem::new(im_str!("Export")).build(&ui, || {
+                        ui.text_wrapped(im_str!("Exports are built from every script in the dumps folder and written next to them"));
+
+                        if ui.small_button(im_str!("Disassembly")) {
+                            ui_state.start_export(|| match export_disassembly() {
+                                Ok(count) => format!("Wrote .bbs sources for {} scripts", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        if ui.small_button(im_str!("Pseudo-code")) {
+                            ui_state.start_export(|| match export_pseudo_code() {
+                                Ok(count) => format!("Wrote pseudo-code for {} scripts", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        if ui.small_button(im_str!("Documents")) {
+                            ui_state.start_export(|| match export_documents() {
+                                Ok(count) => format!("Wrote JSON and YAML documents for {} scripts", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        if ui.small_button(im_str!("Call Graph")) {
+                            ui_state.start_export(|| match export_xrefs() {
+                                Ok(count) => format!("Wrote call graphs for {} characters", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        if ui.small_button(im_str!("String Tables")) {
+                            ui_state.start_export(|| match export_strings() {
+                                Ok(count) => format!("Wrote string tables for {} scripts", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        if ui.small_button(im_str!("Frame Data")) {
+                            ui_state.start_export(|| match export_frame_data() {
+                                Ok(count) => format!("Wrote frame data for {} scripts", count),
+                                Err(e) => format!("Export failed: {}", e),
+                            });
+                        }
+
+                        ui.separator();
+                        ui.text_wrapped(im_str!("Copies a state and everything it needs from one dumped character into another, the result is written to a new folder in the Mods folder"));
+
+                        ui.input_text(im_str!("State"), &mut ui_state.transplant_state).build();
+                        ui.input_text(im_str!("From"), &mut ui_state.transplant_donor).build();
+                        ui.input_text(im_str!("Into"), &mut ui_state.transplant_target).build();
+
+                        if ui.small_button(im_str!("Transplant")) {
+                            let state = ui_state.transplant_state.to_str().to_string();
+                            let donor = ui_state.transplant_donor.to_str().to_string();
+                            let target = ui_state.transplant_target.to_str().to_string();
+
+                            ui_state.start_export(move || match transplant_dumps(&state, &donor, &target) {
+                                Ok((folder, transplanted)) => {
+                                    let mut status = format!(
+                                        "Copied {} functions to `{}`",
+                                        transplanted.copied.len(),
+                                        folder.display()
+                                    );
+                                    for (from, to) in &transplanted.renamed {
+                                        status.push_str(&format!("\n{} renamed to {}", from, to));
+                                    }
+                                    status
+                                }
+                                Err(e) => format!("Transplant failed: {}", e),
+                            });
+                        }
+
+                        ui.text_wrapped(&ImString::new(ui_state.export_status.as_str()));
+                    });
+
+                    TabItem::new(im_str!("Cache")).build(&ui, || {
+                        let mut cache = global::MOD_CACHE.lock();
+
+                        ui.text(im_str!("Injected"));
+
+                        for (slot, injection) in cache.injections() {
+                            ui.text(format!("{}: {}", slot, injection.script));
+
+                            for file in &injection.files {
+                                ui.bullet_text(&im_str!("{} sha256 {}", file.path.display(), &file.hash[..12]));
+
+                                if ui.is_item_hovered() {
+                                    ui.tooltip_text(&file.hash);
+                                }
+                            }
+                        }
+
+                        ui.separator();
+                        ui.text(im_str!("Cached files"));
+                        ui.same_line(0.0);
+
+                        if ui.small_button(im_str!("Clear")) {
+                            debug!("Clearing mod cache");
+                            cache.clear();
+                        }
+
+                        ui.text_wrapped(im_str!("A file is only read from disk again once it changes"));
+
+                        for (path, file) in cache.files() {
+                            ui.text(path.display().to_string());
+                            ui.bullet_text(&im_str!(
+                                "{} bytes, sha256 {}, read from disk {} times",
+                                file.size,
+                                &file.hash[..12],
+                                file.reads
+                            ));
+
+                            if ui.is_item_hovered() {
+                                ui.tooltip_text(&file.hash);
+                            }
+                        }
                     });
 
                     #[cfg(feature = "save-state")]
@@ -51,15 +285,76 @@ pub fn ui_loop(ui: Ui) -> Ui {
                         }
                     });
 
-                    TabItem::new(im_str!("Help"))
-                        .build(&ui, || ui.bullet_text(im_str!("F1: Show/Hide menu")));
+                    TabItem::new(im_str!("Help")).build(&ui, || {
+                        ui.bullet_text(im_str!("F1: Show/Hide menu"));
+
+                        ui.separator();
+                        ui.text(im_str!("Characters (name, shortname, mod file prefix)"));
+
+                        for character in global::ROSTER.characters() {
+                            ui.bullet_text(&im_str!(
+                                "{}: {}, {}",
+                                character.name,
+                                character.shortname,
+                                character.file_prefix()
+                            ));
+                        }
+                    });
                 })
             });
     }
     ui
 }
 
+/// Results past this many don't get drawn, the full count is still shown
+const MAX_SEARCH_RESULTS: usize = 500;
+
 struct GuiState {
     pub display_ui: bool,
+    pub search_kind: QueryKind,
+    pub search_text: ImString,
+    /// Match count followed by one line per match, or an error
+    pub search_results: Vec<String>,
+    /// Search running in the background
+    pub search_task: Option<Receiver<Vec<String>>>,
+    /// Result of the last export or transplant
+    pub export_status: String,
+    /// Export or transplant running in the background, only one runs at a time
+    pub export_task: Option<Receiver<String>>,
+    pub transplant_state: ImString,
+    /// File stems of the dumped main scripts, e.g. `ky`
+    pub transplant_donor: ImString,
+    pub transplant_target: ImString,
 }
 unsafe impl Send for GuiState {}
+
+impl GuiState {
+    /// Starts an export or transplant unless one is already running, `export` returns the status to show
+    fn start_export(&mut self, export: impl FnOnce() -> String + Send + 'static) {
+        if self.export_task.is_none() {
+            self.export_status = "Working...".into();
+            self.export_task = Some(run_in_background(export));
+        }
+    }
+}
+
+/// Runs `task` on a worker thread so it doesn't hold up rendering, its result shows up on the receiver
+fn run_in_background<T: Send + 'static>(task: impl FnOnce() -> T + Send + 'static) -> Receiver<T> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(task());
+    });
+    receiver
+}
+
+/// Takes the result of a finished background task, `failed` stands in for tasks that panicked
+fn poll_task<T>(task: &mut Option<Receiver<T>>, failed: impl FnOnce() -> T) -> Option<T> {
+    let result = match task.as_ref()?.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return None,
+        Err(TryRecvError::Disconnected) => failed(),
+    };
+
+    *task = None;
+    Some(result)
+}